@@ -72,7 +72,7 @@ type Route
 type DatabaseState
     = Waiting
     | Uploading Float
-    | Done WorkSheet
+    | Done
     | Fail
 
 
@@ -182,7 +182,7 @@ actionsDecoder =
 type Msg
     = GotFiles (List File)
     | GotProgress Http.Progress
-    | Uploaded (Result Http.Error WorkSheet)
+    | Uploaded (Result Http.Error ())
     | OnUrlRequest Browser.UrlRequest
     | OnUrlChange Url
     | ReceiveDate Date
@@ -200,7 +200,7 @@ update msg model =
                 , url = "/api/upload"
                 , headers = []
                 , body = Http.multipartBody (List.map (Http.filePart "file") files)
-                , expect = Http.expectJson Uploaded workSheetDecoder
+                , expect = Http.expectWhatever Uploaded
                 , timeout = Nothing
                 , tracker = Just "upload"
                 }
@@ -216,8 +216,8 @@ update msg model =
 
         Uploaded result ->
             case result of
-                Ok response ->
-                    ( { model | page = UploadingDatabase (Done response) }
+                Ok _ ->
+                    ( { model | page = UploadingDatabase Done }
                     , Browser.Navigation.pushUrl
                         model.key
                         (getPath SelectAvailableDay)
@@ -392,7 +392,7 @@ uploadView database =
         Uploading fraction ->
             h1 [] [ text (String.fromInt (round (100 * fraction)) ++ "%") ]
 
-        Done _ ->
+        Done ->
             Html.div [] [ h1 [] [ text "DONE" ] ]
 
         Fail ->
@@ -0,0 +1,37 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum ParseError {
+    Open(io::Error),
+    MissingTable { table: String },
+    Table { table: String, cause: io::Error },
+    InvalidValue { table: String, row: usize, column: String, found: String },
+}
+
+impl ParseError {
+    pub fn table(table: &str, cause: io::Error) -> ParseError {
+        match cause.kind() {
+            io::ErrorKind::NotFound => ParseError::MissingTable { table: table.to_string() },
+            _ => ParseError::Table { table: table.to_string(), cause },
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::Open(cause) => write!(f, "could not open database: {}", cause),
+            ParseError::MissingTable { table } => write!(f, "database has no table {}", table),
+            ParseError::Table { table, cause } => write!(f, "could not read table {}: {}", table, cause),
+            ParseError::InvalidValue { table, row, column, found } => write!(
+                f,
+                "invalid {} in row {} of table {}, found {}",
+                column, row, table, found
+            ),
+        }
+    }
+}
+
+impl Error for ParseError {}
@@ -1,19 +1,22 @@
-extern crate csv;
 #[macro_use]
 extern crate serde_json;
 
 #[macro_use]
 extern crate serde_derive;
 
-use std::process::Command;
-use stringreader::StringReader;
+pub mod mdb;
+mod error;
+
 use std::collections::HashMap;
 use chrono::NaiveDateTime;
 use serde::ser::{Serialize, Serializer, SerializeStruct};
 use std::path::PathBuf;
+use mdb::{Database, Row, Value};
+
+pub use error::ParseError;
 
 #[allow(non_snake_case)]
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct TimeEntryRaw {
     Date: String,
     Time: String,
@@ -24,7 +27,7 @@ pub struct TimeEntryRaw {
 
 
 #[allow(non_snake_case)]
-#[derive(Debug, Deserialize)]
+#[derive(Debug)]
 pub struct ActionsRaw {
     ACT_ID: u32,
     ACT_Name: String,
@@ -32,19 +35,27 @@ pub struct ActionsRaw {
 
 
 #[allow(non_snake_case)]
-#[derive(Debug, Deserialize)]
+#[derive(Debug)]
 pub struct EmployeeRaw {
     EN: u32,
     Name: String,
 }
 
+// The name of an employee or action that is not in the master data
+pub const UNKNOWN: &str = "Onbekend";
+
 pub type Employees = HashMap<u32, String>;
 pub type Actions = HashMap<u32, String>;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TimeRowEvent {
     pub id: u32,
+    // Events imported before the ids were kept only have the names
+    #[serde(default)]
+    pub employee_id: Option<u32>,
     pub employee: String,
+    #[serde(default)]
+    pub action_id: Option<u32>,
     pub action: String,
     pub timestamp: NaiveDateTime,
 }
@@ -53,116 +64,328 @@ impl TimeRowEvent {
     pub fn stringify(&self) -> String {
         serde_json::to_string(self).expect("stringify failed")
     }
+
+    // Whether both rows record the same employee clocking at the same moment.
+    // Names are only compared when either side was imported before the ids
+    // were kept.
+    pub fn same_moment(&self, other: &TimeRowEvent) -> bool {
+        let same_employee = match (self.employee_id, other.employee_id) {
+            (Some(a), Some(b)) => a == b,
+            _ => self.employee == other.employee,
+        };
+
+        self.id == other.id && self.timestamp == other.timestamp && same_employee
+    }
+
+    pub fn same_punch(&self, other: &TimeRowEvent) -> bool {
+        let same_action = match (self.action_id, other.action_id) {
+            (Some(a), Some(b)) => a == b,
+            _ => self.action == other.action,
+        };
+
+        self.same_moment(other) && same_action
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct MasterData {
+    pub employees: Employees,
+    pub actions: Actions,
+}
+
+impl MasterData {
+    // Names are looked up by id, the name stored in the event is what was
+    // known when it was imported and only used when the id is unknown.
+    pub fn resolve(&self, time_row: &mut TimeRowEvent) {
+        if let Some(name) = time_row.employee_id.and_then(|id| self.employees.get(&id)) {
+            time_row.employee = name.clone();
+        }
+
+        if let Some(name) = time_row.action_id.and_then(|id| self.actions.get(&id)) {
+            time_row.action = name.clone();
+        }
+    }
+}
+
+// The master data of every terminal, an id registered at one terminal means
+// nothing at another.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct MasterDataPerSource {
+    pub sources: HashMap<String, MasterData>,
+}
+
+impl MasterDataPerSource {
+    pub fn source(&self, source: &str) -> Option<&MasterData> {
+        self.sources.get(source)
+    }
+
+    pub fn source_mut(&mut self, source: &str) -> &mut MasterData {
+        self.sources.entry(source.to_string()).or_default()
+    }
+
+    pub fn resolve(&self, source: &str, time_row: &mut TimeRowEvent) {
+        if let Some(master_data) = self.source(source) {
+            master_data.resolve(time_row);
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RejectedRow {
+    pub id: u32,
+    pub raw: TimeEntryRaw,
+    pub reason: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ParsedDb {
+    // Rows past the last imported TRD_RunNr
+    pub time_rows: Vec<TimeRowEvent>,
+    // Rows up to and including the last imported TRD_RunNr
+    pub imported_rows: Vec<TimeRowEvent>,
+    pub rejected_rows: Vec<RejectedRow>,
+    pub master_data: MasterData,
 }
 
 
-pub fn parse_db(path_to_db: &PathBuf) -> Vec<TimeRowEvent> {
-    let time = read_time_entries(path_to_db);
-    let employees = get_employees(path_to_db);
-    let actions = get_actions(path_to_db);
-    let default_value = &String::from("Onbekend");
+pub fn parse_db(path_to_db: &PathBuf, last_run_nr: Option<u32>) -> Result<ParsedDb, ParseError> {
+    let db = Database::open(path_to_db).map_err(ParseError::Open)?;
+    let time = read_time_entries(&db)?;
+    let employees = get_employees(&db)?;
+    let actions = get_actions(&db)?;
+    let default_value = &String::from(UNKNOWN);
+
+    let mut time_rows = vec![];
+    let mut imported_rows = vec![];
+    let mut rejected_rows = vec![];
 
     time
         .into_iter()
-        .map(|time_entry| {
-            let employee = employees
-                .get(&time_entry.Empl)
-                .unwrap_or(default_value)
-                .clone();
-
-            let action = actions
-                .get(&time_entry.Action)
-                .unwrap_or(default_value)
-                .clone();
-
-            let date = chrono::NaiveDate::parse_from_str(&time_entry.Date, "%Y%m%d")
-                .unwrap_or(chrono::NaiveDate::from_ymd(1970, 1, 1));
-
-            let time = chrono::NaiveTime::parse_from_str(&time_entry.Time, "%k%M%S")
-                .unwrap_or(chrono::NaiveTime::from_hms(0, 0, 0));
-
-            let timestamp = NaiveDateTime::new(date, time);
-
-            TimeRowEvent {
-                id: time_entry.TRD_RunNr,
-                employee,
-                action,
-                timestamp,
+        .for_each(|time_entry| {
+            match parse_timestamp(&time_entry) {
+                Ok(timestamp) => {
+                    let employee = employees
+                        .get(&time_entry.Empl)
+                        .unwrap_or(default_value)
+                        .clone();
+
+                    let action = actions
+                        .get(&time_entry.Action)
+                        .unwrap_or(default_value)
+                        .clone();
+
+                    let time_row = TimeRowEvent {
+                        id: time_entry.TRD_RunNr,
+                        employee_id: Some(time_entry.Empl),
+                        employee,
+                        action_id: Some(time_entry.Action),
+                        action,
+                        timestamp,
+                    };
+
+                    match last_run_nr {
+                        Some(last) if time_row.id <= last => imported_rows.push(time_row),
+                        _ => time_rows.push(time_row),
+                    }
+                }
+                Err(reason) => {
+                    // Never guess a timestamp, a wrong one ends up in someone's worksheet
+                    rejected_rows.push(RejectedRow {
+                        id: time_entry.TRD_RunNr,
+                        raw: time_entry,
+                        reason,
+                    });
+                }
             }
-        })
-        .collect()
+        });
+
+    Ok(ParsedDb {
+        time_rows,
+        imported_rows,
+        rejected_rows,
+        master_data: MasterData { employees, actions },
+    })
 }
 
-fn read_time_entries(path_to_db: &PathBuf) -> Vec<TimeEntryRaw> {
-    let result = Command::new("mdb-export")
-        .arg(path_to_db)
-        .arg("Time_RawData")
-        .output()
-        .expect("get csv of data").stdout;
-    let csv = std::str::from_utf8(&result).expect("Can't stringify stdout");
-    let streader = StringReader::new(csv);
+fn parse_timestamp(time_entry: &TimeEntryRaw) -> Result<NaiveDateTime, String> {
+    let date = chrono::NaiveDate::parse_from_str(&time_entry.Date, "%Y%m%d")
+        .map_err(|_| format!("invalid date {:?}", time_entry.Date))?;
 
-    let mut rdr = csv::Reader::from_reader(streader);
+    let mut time = time_entry.Time.clone();
+    if time.len() == 5 {
+        time.insert(0, '0');
+    }
+    let time = chrono::NaiveTime::parse_from_str(&time, "%k%M%S")
+        .map_err(|_| format!("invalid time {:?}", time_entry.Time))?;
 
-    rdr.deserialize()
-        .into_iter()
-        .map(|record| {
-            let mut time_entry: TimeEntryRaw = record.expect("Invalid TimeEntryRaw");
-            if time_entry.Time.len() == 5 {
-                let mut time = String::from("0");
-                time.push_str(&time_entry.Time);
-                time_entry.Time = time;
-            }
-            time_entry
+    Ok(NaiveDateTime::new(date, time))
+}
+
+fn read_table(db: &Database, table: &str) -> Result<Vec<Row>, ParseError> {
+    db.table(table)
+        .and_then(|t| t.rows())
+        .map_err(|cause| ParseError::table(table, cause))
+}
+
+fn column<T>(table: &str, index: usize, row: &Row, name: &str, convert: fn(&Value) -> Option<T>) -> Result<T, ParseError> {
+    let value = row.get(name);
+
+    value.and_then(convert).ok_or_else(|| ParseError::InvalidValue {
+        table: table.to_string(),
+        row: index + 1,
+        column: name.to_string(),
+        found: value.map(|v| format!("{:?}", v)).unwrap_or_else(|| "no such column".to_string()),
+    })
+}
+
+fn read_time_entries(db: &Database) -> Result<Vec<TimeEntryRaw>, ParseError> {
+    let table = "Time_RawData";
+
+    read_table(db, table)?
+        .iter()
+        .enumerate()
+        .map(|(index, row)| {
+            Ok(TimeEntryRaw {
+                Date: column(table, index, row, "Date", Value::to_text)?,
+                Time: column(table, index, row, "Time", Value::to_text)?,
+                Empl: column(table, index, row, "Empl", Value::to_u32)?,
+                Action: column(table, index, row, "Action", Value::to_u32)?,
+                TRD_RunNr: column(table, index, row, "TRD_RunNr", Value::to_u32)?,
+            })
         })
         .collect()
 }
 
-pub fn get_employees(path_to_db: &PathBuf) -> Employees {
-    let result = Command::new("mdb-export")
-        .arg(path_to_db)
-        .arg("PersonelData")
-        .output()
-        .expect("get csv of data").stdout;
-    let csv = std::str::from_utf8(&result).expect("Can't stringify stdout");
-    let streader = StringReader::new(csv);
+pub fn get_employees(db: &Database) -> Result<Employees, ParseError> {
+    let table = "PersonelData";
 
-    csv::Reader::from_reader(streader)
-        .deserialize()
-        .into_iter()
-        .map(|record| record.expect("Invalid EmployeeRaw"))
-        .fold(HashMap::new(), |mut map, record: EmployeeRaw| {
-            map.insert(record.EN, record.Name);
+    read_table(db, table)?
+        .iter()
+        .enumerate()
+        .map(|(index, row)| {
+            Ok(EmployeeRaw {
+                EN: column(table, index, row, "EN", Value::to_u32)?,
+                Name: column(table, index, row, "Name", Value::to_text)?,
+            })
+        })
+        .collect::<Result<Vec<EmployeeRaw>, ParseError>>()
+        .map(|records| {
+            records.into_iter().fold(HashMap::new(), |mut map, record| {
+                map.insert(record.EN, record.Name);
 
-            map
+                map
+            })
         })
 }
 
-pub fn get_actions(path_to_db: &PathBuf) -> Actions {
-    let result = Command::new("mdb-export")
-        .arg(path_to_db)
-        .arg("Actions")
-        .output()
-        .expect("get csv of data").stdout;
-    let csv = std::str::from_utf8(&result).expect("Can't stringify stdout");
-    let streader = StringReader::new(csv);
+pub fn get_actions(db: &Database) -> Result<Actions, ParseError> {
+    let table = "Actions";
 
-    csv::Reader::from_reader(streader)
-        .deserialize()
-        .into_iter()
-        .map(|record| record.expect("Invalid ActionRaw"))
-        .fold(HashMap::new(), |mut map, record: ActionsRaw| {
-            map.insert(record.ACT_ID, record.ACT_Name);
+    read_table(db, table)?
+        .iter()
+        .enumerate()
+        .map(|(index, row)| {
+            Ok(ActionsRaw {
+                ACT_ID: column(table, index, row, "ACT_ID", Value::to_u32)?,
+                ACT_Name: column(table, index, row, "ACT_Name", Value::to_text)?,
+            })
+        })
+        .collect::<Result<Vec<ActionsRaw>, ParseError>>()
+        .map(|records| {
+            records.into_iter().fold(HashMap::new(), |mut map, record| {
+                map.insert(record.ACT_ID, record.ACT_Name);
 
-            map
+                map
+            })
         })
 }
 
 
 #[cfg(test)]
 mod tests {
+    use crate::mdb::{Row, Value};
+    use crate::{MasterData, ParseError, TimeEntryRaw, TimeRowEvent};
+    use chrono::NaiveDate;
+
+    fn time_entry(date: &str, time: &str) -> TimeEntryRaw {
+        TimeEntryRaw {
+            Date: date.to_string(),
+            Time: time.to_string(),
+            Empl: 1,
+            Action: 2,
+            TRD_RunNr: 173,
+        }
+    }
+
     #[test]
     fn it_works() {
         assert_eq!(2 + 2, 4);
     }
+
+    #[test]
+    fn it_should_name_table_row_and_column_of_invalid_values() {
+        let mut row = Row::new();
+        row.insert("Empl".to_string(), Value::Text("Michel".to_string()));
+
+        let error = crate::column("Time_RawData", 4, &row, "Empl", Value::to_u32).unwrap_err();
+
+        match error {
+            ParseError::InvalidValue { table, row, column, .. } => {
+                assert_eq!(table, "Time_RawData");
+                assert_eq!(row, 5);
+                assert_eq!(column, "Empl");
+            }
+            other => panic!("unexpected error {:?}", other),
+        }
+    }
+
+    #[test]
+    fn it_should_parse_timestamps_with_a_single_digit_hour() {
+        let timestamp = crate::parse_timestamp(&time_entry("20190102", "70116")).unwrap();
+
+        assert_eq!(timestamp, NaiveDate::from_ymd_opt(2019, 1, 2).unwrap().and_hms_opt(7, 1, 16).unwrap());
+    }
+
+    #[test]
+    fn it_should_reject_unparseable_dates_and_times() {
+        assert_eq!(
+            crate::parse_timestamp(&time_entry("20191302", "70116")),
+            Err("invalid date \"20191302\"".to_string())
+        );
+        assert_eq!(
+            crate::parse_timestamp(&time_entry("20190102", "")),
+            Err("invalid time \"\"".to_string())
+        );
+    }
+
+    #[test]
+    fn it_should_resolve_names_from_master_data() {
+        let mut master_data = MasterData::default();
+        master_data.employees.insert(1, "Michel".to_string());
+        let mut time_row: TimeRowEvent = serde_json::from_str(
+            "{\"id\":173,\"employee_id\":1,\"employee\":\"Onbekend\",\"action_id\":2,\"action\":\"Kas\",\"timestamp\":\"2019-01-02T07:01:16\"}"
+        ).unwrap();
+
+        master_data.resolve(&mut time_row);
+
+        assert_eq!(time_row.employee, "Michel");
+        assert_eq!(time_row.action, "Kas");
+    }
+
+    #[test]
+    fn it_should_compare_punches_by_id_and_fall_back_to_names() {
+        let with_ids: TimeRowEvent = serde_json::from_str(
+            "{\"id\":173,\"employee_id\":1,\"employee\":\"Michel\",\"action_id\":2,\"action\":\"Kas\",\"timestamp\":\"2019-01-02T07:01:16\"}"
+        ).unwrap();
+        let renamed: TimeRowEvent = serde_json::from_str(
+            "{\"id\":173,\"employee_id\":1,\"employee\":\"Michel van der Hulst\",\"action_id\":2,\"action\":\"Kas\",\"timestamp\":\"2019-01-02T07:01:16\"}"
+        ).unwrap();
+        let legacy: TimeRowEvent = serde_json::from_str(
+            "{\"id\":173,\"employee\":\"Michel\",\"action\":\"Kas\",\"timestamp\":\"2019-01-02T07:01:16\"}"
+        ).unwrap();
+
+        assert!(with_ids.same_punch(&renamed));
+        assert!(with_ids.same_punch(&legacy));
+        assert!(!renamed.same_punch(&legacy));
+    }
 }
@@ -0,0 +1,969 @@
+// Reader for Access (Jet3 / Jet4) database files.
+//
+// Only what is needed to iterate the rows of a table is implemented: the
+// system catalog (MSysObjects) is used to find a table definition page, the
+// table's usage map lists its data pages, and every row on those pages is
+// cracked into named column values.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use chrono::{Duration, NaiveDate, NaiveDateTime};
+
+const PAGE_DATA: u8 = 0x01;
+const PAGE_TABLE_DEFINITION: u8 = 0x02;
+const CATALOG_PAGE: u32 = 2;
+const CATALOG_TYPE_TABLE: i16 = 1;
+
+const ROW_OFFSET_MASK: u16 = 0x1fff;
+const ROW_DELETED: u16 = 0x8000;
+const ROW_OVERFLOW: u16 = 0x4000;
+
+const COLUMN_FIXED: u8 = 0x01;
+
+const TYPE_BOOL: u8 = 0x01;
+const TYPE_BYTE: u8 = 0x02;
+const TYPE_INT: u8 = 0x03;
+const TYPE_LONG: u8 = 0x04;
+const TYPE_MONEY: u8 = 0x05;
+const TYPE_FLOAT: u8 = 0x06;
+const TYPE_DOUBLE: u8 = 0x07;
+const TYPE_DATETIME: u8 = 0x08;
+const TYPE_TEXT: u8 = 0x0a;
+const TYPE_MEMO: u8 = 0x0c;
+
+const MEMO_INLINE: u32 = 0x8000_0000;
+const MEMO_SINGLE_PAGE: u32 = 0x4000_0000;
+const MEMO_LENGTH_MASK: u32 = 0x3fff_ffff;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Version {
+    Jet3,
+    Jet4,
+}
+
+// Offsets that differ between the Jet3 and Jet4 page layouts.
+struct Layout {
+    page_size: usize,
+    row_count_offset: usize,
+    num_cols_offset: usize,
+    num_real_indexes_offset: usize,
+    usage_map_offset: usize,
+    columns_offset: usize,
+    real_index_entry_size: usize,
+    column_entry_size: usize,
+    column_num_offset: usize,
+    column_var_offset: usize,
+    column_flags_offset: usize,
+    column_fixed_offset: usize,
+    column_size_offset: usize,
+}
+
+const JET3: Layout = Layout {
+    page_size: 2048,
+    row_count_offset: 0x08,
+    num_cols_offset: 25,
+    num_real_indexes_offset: 31,
+    usage_map_offset: 35,
+    columns_offset: 43,
+    real_index_entry_size: 8,
+    column_entry_size: 18,
+    column_num_offset: 1,
+    column_var_offset: 3,
+    column_flags_offset: 13,
+    column_fixed_offset: 14,
+    column_size_offset: 16,
+};
+
+const JET4: Layout = Layout {
+    page_size: 4096,
+    row_count_offset: 0x0c,
+    num_cols_offset: 45,
+    num_real_indexes_offset: 51,
+    usage_map_offset: 55,
+    columns_offset: 63,
+    real_index_entry_size: 12,
+    column_entry_size: 25,
+    column_num_offset: 5,
+    column_var_offset: 7,
+    column_flags_offset: 15,
+    column_fixed_offset: 21,
+    column_size_offset: 23,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Byte(u8),
+    Int(i16),
+    Long(i32),
+    Money(i64),
+    Float(f32),
+    Double(f64),
+    DateTime(NaiveDateTime),
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+impl Value {
+    pub fn to_text(&self) -> Option<String> {
+        match self {
+            Value::Text(text) => Some(text.clone()),
+            Value::Byte(n) => Some(n.to_string()),
+            Value::Int(n) => Some(n.to_string()),
+            Value::Long(n) => Some(n.to_string()),
+            _ => None,
+        }
+    }
+
+    pub fn to_u32(&self) -> Option<u32> {
+        match self {
+            Value::Byte(n) => Some(u32::from(*n)),
+            Value::Int(n) if *n >= 0 => Some(*n as u32),
+            Value::Long(n) if *n >= 0 => Some(*n as u32),
+            Value::Text(text) => text.trim().parse().ok(),
+            _ => None,
+        }
+    }
+}
+
+pub type Row = HashMap<String, Value>;
+
+#[derive(Debug, Clone)]
+pub struct Column {
+    pub name: String,
+    pub column_type: u8,
+    number: u16,
+    var_index: u16,
+    fixed_offset: u16,
+    size: u16,
+    fixed: bool,
+}
+
+pub struct Database {
+    version: Version,
+    layout: &'static Layout,
+    data: Vec<u8>,
+}
+
+pub struct Table<'a> {
+    db: &'a Database,
+    definition_page: u32,
+    num_var_cols: u16,
+    columns: Vec<Column>,
+    usage_map: u32,
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn u16_at(buf: &[u8], offset: usize) -> io::Result<u16> {
+    buf.get(offset..offset + 2)
+        .map(|b| u16::from(b[0]) | u16::from(b[1]) << 8)
+        .ok_or_else(|| invalid(format!("read past end of buffer at {}", offset)))
+}
+
+fn u32_at(buf: &[u8], offset: usize) -> io::Result<u32> {
+    Ok(u32::from(u16_at(buf, offset)?) | u32::from(u16_at(buf, offset + 2)?) << 16)
+}
+
+fn u64_at(buf: &[u8], offset: usize) -> io::Result<u64> {
+    Ok(u64::from(u32_at(buf, offset)?) | u64::from(u32_at(buf, offset + 4)?) << 32)
+}
+
+impl Database {
+    pub fn open(path: &Path) -> io::Result<Database> {
+        Database::from_bytes(fs::read(path)?)
+    }
+
+    pub fn from_bytes(data: Vec<u8>) -> io::Result<Database> {
+        if data.len() < 0x15 || data[0..4] != [0x00, 0x01, 0x00, 0x00] {
+            return Err(invalid("not an Access database".to_string()));
+        }
+
+        let version = match data[0x14] {
+            0 => Version::Jet3,
+            _ => Version::Jet4,
+        };
+        let layout = match version {
+            Version::Jet3 => &JET3,
+            Version::Jet4 => &JET4,
+        };
+
+        Ok(Database { version, layout, data })
+    }
+
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    pub fn table(&self, name: &str) -> io::Result<Table<'_>> {
+        let catalog = self.table_at(CATALOG_PAGE)?;
+
+        let definition_page = catalog
+            .rows()?
+            .into_iter()
+            .find(|row| {
+                let is_table = match row.get("Type") {
+                    Some(Value::Int(object_type)) => object_type & 0x7fff == CATALOG_TYPE_TABLE,
+                    _ => false,
+                };
+                is_table && row.get("Name") == Some(&Value::Text(name.to_string()))
+            })
+            .and_then(|row| match row.get("Id") {
+                Some(Value::Long(id)) => Some(*id as u32 & 0x00ff_ffff),
+                _ => None,
+            })
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("no table named {}", name)))?;
+
+        self.table_at(definition_page)
+    }
+
+    fn page(&self, number: u32) -> io::Result<&[u8]> {
+        let size = self.layout.page_size;
+        let start = number as usize * size;
+
+        self.data
+            .get(start..start + size)
+            .ok_or_else(|| invalid(format!("page {} is past the end of the file", number)))
+    }
+
+    // A table definition may span several pages; continuation pages are
+    // linked through the next page pointer and start after an 8 byte header.
+    fn table_definition(&self, number: u32) -> io::Result<Vec<u8>> {
+        let first = self.page(number)?;
+        if first[0] != PAGE_TABLE_DEFINITION {
+            return Err(invalid(format!("page {} is not a table definition", number)));
+        }
+
+        let mut definition = first.to_vec();
+        let mut visited = HashSet::new();
+        let mut next = u32_at(first, 4)?;
+        while next != 0 {
+            if !visited.insert(next) {
+                return Err(invalid(format!("table definition {} links back to page {}", number, next)));
+            }
+            let page = self.page(next)?;
+            definition.extend_from_slice(&page[8..]);
+            next = u32_at(page, 4)?;
+        }
+
+        Ok(definition)
+    }
+
+    fn table_at(&self, definition_page: u32) -> io::Result<Table<'_>> {
+        let layout = self.layout;
+        let definition = self.table_definition(definition_page)?;
+
+        let num_var_cols = u16_at(&definition, layout.num_cols_offset - 2)?;
+        let num_cols = u16_at(&definition, layout.num_cols_offset)? as usize;
+        let num_real_indexes = u32_at(&definition, layout.num_real_indexes_offset)? as usize;
+        let usage_map = u32_at(&definition, layout.usage_map_offset)?;
+
+        let mut offset = layout.columns_offset + num_real_indexes * layout.real_index_entry_size;
+        let mut columns = Vec::with_capacity(num_cols);
+        for _ in 0..num_cols {
+            let entry = definition
+                .get(offset..offset + layout.column_entry_size)
+                .ok_or_else(|| invalid(format!("truncated column list in table {}", definition_page)))?;
+
+            columns.push(Column {
+                name: String::new(),
+                column_type: entry[0],
+                number: u16_at(entry, layout.column_num_offset)?,
+                var_index: u16_at(entry, layout.column_var_offset)?,
+                fixed_offset: u16_at(entry, layout.column_fixed_offset)?,
+                size: u16_at(entry, layout.column_size_offset)?,
+                fixed: entry[layout.column_flags_offset] & COLUMN_FIXED != 0,
+            });
+            offset += layout.column_entry_size;
+        }
+
+        for column in columns.iter_mut() {
+            let (length, width) = match self.version {
+                Version::Jet3 => (*definition.get(offset).unwrap_or(&0) as usize, 1),
+                Version::Jet4 => (u16_at(&definition, offset)? as usize, 2),
+            };
+            let name = definition
+                .get(offset + width..offset + width + length)
+                .ok_or_else(|| invalid(format!("truncated column names in table {}", definition_page)))?;
+
+            column.name = self.decode_text(name);
+            offset += width + length;
+        }
+
+        columns.sort_by_key(|column| column.number);
+
+        Ok(Table {
+            db: self,
+            definition_page,
+            num_var_cols,
+            columns,
+            usage_map,
+        })
+    }
+
+    // Returns the bytes of a row together with its flags. Rows are stored
+    // back to front, so a row ends where the previous one starts.
+    fn row(&self, page: &[u8], row: usize) -> io::Result<(u16, std::ops::Range<usize>)> {
+        let offsets = self.layout.row_count_offset + 2;
+        let raw = u16_at(page, offsets + row * 2)?;
+        let end = if row == 0 {
+            self.layout.page_size
+        } else {
+            (u16_at(page, offsets + (row - 1) * 2)? & ROW_OFFSET_MASK) as usize
+        };
+        let start = (raw & ROW_OFFSET_MASK) as usize;
+
+        if start > end {
+            return Err(invalid(format!("row {} has a negative length", row)));
+        }
+        if end > page.len() {
+            return Err(invalid(format!("row {} ends past its page", row)));
+        }
+
+        Ok((raw & !ROW_OFFSET_MASK, start..end))
+    }
+
+    // Resolves a row pointer: the page number in the upper three bytes and
+    // the row number in the lowest byte.
+    fn pointed_row(&self, pointer: u32) -> io::Result<&[u8]> {
+        let page = self.page(pointer >> 8)?;
+        let (_, range) = self.row(page, (pointer & 0xff) as usize)?;
+
+        Ok(&page[range])
+    }
+
+    fn decode_text(&self, bytes: &[u8]) -> String {
+        match self.version {
+            Version::Jet3 => bytes.iter().map(|&b| b as char).collect(),
+            Version::Jet4 => decode_ucs2(bytes),
+        }
+    }
+
+    fn decode_memo(&self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        let header = u32_at(bytes, 0)?;
+        let length = (header & MEMO_LENGTH_MASK) as usize;
+
+        if header & MEMO_INLINE != 0 {
+            return bytes
+                .get(12..12 + length)
+                .map(|data| data.to_vec())
+                .ok_or_else(|| invalid("truncated inline memo".to_string()));
+        }
+
+        let pointer = u32_at(bytes, 4)?;
+        if header & MEMO_SINGLE_PAGE != 0 {
+            let row = self.pointed_row(pointer)?;
+            return Ok(row[..length.min(row.len())].to_vec());
+        }
+
+        // Long values spanning several pages: every part starts with a
+        // pointer to the next one.
+        let mut data = Vec::with_capacity(length);
+        let mut visited = HashSet::new();
+        let mut next = pointer;
+        while next != 0 && data.len() < length {
+            if !visited.insert(next) {
+                return Err(invalid(format!("memo links back to row {:#x}", next)));
+            }
+            let row = self.pointed_row(next)?;
+            next = u32_at(row, 0)?;
+            data.extend_from_slice(&row[4..]);
+        }
+        data.truncate(length);
+
+        Ok(data)
+    }
+}
+
+impl<'a> Table<'a> {
+    pub fn columns(&self) -> &[Column] {
+        &self.columns
+    }
+
+    pub fn rows(&self) -> io::Result<Vec<Row>> {
+        let db = self.db;
+        let mut pointers = vec![];
+        let mut locations = vec![];
+
+        for page_number in self.data_pages()? {
+            let page = db.page(page_number)?;
+            if page[0] != PAGE_DATA || u32_at(page, 4)? != self.definition_page {
+                continue;
+            }
+
+            let count = u16_at(page, db.layout.row_count_offset)? as usize;
+            for row in 0..count {
+                let (flags, range) = db.row(page, row)?;
+                if flags & ROW_DELETED != 0 {
+                    continue;
+                }
+                if flags & ROW_OVERFLOW != 0 {
+                    pointers.push(u32_at(page, range.start)?);
+                } else {
+                    locations.push(page_number << 8 | row as u32);
+                }
+            }
+        }
+
+        // A row that grew too large for its page is moved elsewhere and
+        // replaced by a pointer; only read it through that pointer.
+        let moved: HashSet<u32> = pointers.iter().cloned().collect();
+
+        locations
+            .into_iter()
+            .filter(|location| !moved.contains(location))
+            .chain(pointers)
+            .map(|location| self.crack_row(db.pointed_row(location)?))
+            .collect()
+    }
+
+    fn data_pages(&self) -> io::Result<Vec<u32>> {
+        let db = self.db;
+        let map = db.pointed_row(self.usage_map)?;
+        let mut pages = vec![];
+
+        match map.first() {
+            // Inline map: a start page followed by a bitmap of the pages after it.
+            Some(0) => {
+                let start = u32_at(map, 1)?;
+                for (i, byte) in map[5..].iter().enumerate() {
+                    for bit in 0..8 {
+                        if byte & (1 << bit) != 0 {
+                            pages.push(start + (i * 8 + bit) as u32);
+                        }
+                    }
+                }
+            }
+            // Reference map: a list of bitmap pages, each covering a fixed range of pages.
+            Some(1) => {
+                let per_page = (db.layout.page_size - 4) * 8;
+                for (i, chunk) in map[1..].chunks(4).enumerate() {
+                    let bitmap_page = if chunk.len() == 4 { u32_at(chunk, 0)? } else { 0 };
+                    if bitmap_page == 0 {
+                        continue;
+                    }
+                    let bitmap = db.page(bitmap_page)?;
+                    for (j, byte) in bitmap[4..].iter().enumerate() {
+                        for bit in 0..8 {
+                            if byte & (1 << bit) != 0 {
+                                pages.push((i * per_page + j * 8 + bit) as u32);
+                            }
+                        }
+                    }
+                }
+            }
+            _ => return Err(invalid(format!("unknown usage map in table {}", self.definition_page))),
+        }
+
+        Ok(pages)
+    }
+
+    fn crack_row(&self, row: &[u8]) -> io::Result<Row> {
+        let db = self.db;
+        let len = row.len();
+        let (num_cols, count_size) = match db.version {
+            Version::Jet3 => (*row.first().unwrap_or(&0) as usize, 1),
+            Version::Jet4 => (u16_at(row, 0)? as usize, 2),
+        };
+
+        let bitmask_size = num_cols.div_ceil(8);
+        if len < count_size + bitmask_size {
+            return Err(invalid("row is shorter than its null mask".to_string()));
+        }
+        let null_mask = &row[len - bitmask_size..];
+
+        let var_offsets = if self.num_var_cols > 0 {
+            match db.version {
+                Version::Jet3 => var_offsets_jet3(row, bitmask_size)?,
+                Version::Jet4 => var_offsets_jet4(row, bitmask_size)?,
+            }
+        } else {
+            vec![]
+        };
+        let row_var_cols = var_offsets.len().saturating_sub(1);
+        let row_fixed_cols = num_cols.saturating_sub(row_var_cols);
+
+        let mut fixed_found = 0;
+        let mut values = Row::new();
+        for column in self.columns.iter() {
+            let bit = column.number as usize;
+            let present = null_mask
+                .get(bit / 8)
+                .map(|byte| byte & (1 << (bit % 8)) != 0)
+                .unwrap_or(false);
+
+            // Booleans have no storage of their own, the null bit is the value.
+            if column.column_type == TYPE_BOOL {
+                values.insert(column.name.clone(), Value::Bool(present));
+                continue;
+            }
+
+            let range = if column.fixed && fixed_found < row_fixed_cols {
+                fixed_found += 1;
+                let start = count_size + column.fixed_offset as usize;
+                Some(start..start + column.size as usize)
+            } else if !column.fixed && (column.var_index as usize) < row_var_cols {
+                let index = column.var_index as usize;
+                Some(var_offsets[index] as usize..var_offsets[index + 1] as usize)
+            } else {
+                None
+            };
+
+            let value = match range {
+                Some(range) if present => {
+                    let bytes = row
+                        .get(range)
+                        .ok_or_else(|| invalid(format!("column {} is outside its row", column.name)))?;
+                    self.decode_value(column, bytes)?
+                }
+                _ => Value::Null,
+            };
+            values.insert(column.name.clone(), value);
+        }
+
+        Ok(values)
+    }
+
+    fn decode_value(&self, column: &Column, bytes: &[u8]) -> io::Result<Value> {
+        Ok(match column.column_type {
+            TYPE_BYTE => Value::Byte(*bytes.first().unwrap_or(&0)),
+            TYPE_INT => Value::Int(u16_at(bytes, 0)? as i16),
+            TYPE_LONG => Value::Long(u32_at(bytes, 0)? as i32),
+            TYPE_MONEY => Value::Money(u64_at(bytes, 0)? as i64),
+            TYPE_FLOAT => Value::Float(f32::from_bits(u32_at(bytes, 0)?)),
+            TYPE_DOUBLE => Value::Double(f64::from_bits(u64_at(bytes, 0)?)),
+            TYPE_DATETIME => Value::DateTime(decode_datetime(f64::from_bits(u64_at(bytes, 0)?))?),
+            TYPE_TEXT => Value::Text(self.db.decode_text(bytes)),
+            TYPE_MEMO => Value::Text(self.db.decode_text(&self.db.decode_memo(bytes)?)),
+            _ => Value::Binary(bytes.to_vec()),
+        })
+    }
+}
+
+// Jet4 stores the variable column offsets as 16 bit values in front of the
+// variable column count, last column first.
+fn var_offsets_jet4(row: &[u8], bitmask_size: usize) -> io::Result<Vec<u16>> {
+    let end = row.len().checked_sub(bitmask_size).ok_or_else(offsets_outside_row)?;
+    let count = u16_at(row, end.checked_sub(2).ok_or_else(offsets_outside_row)?)? as usize;
+
+    (0..=count)
+        .map(|i| {
+            end.checked_sub(4 + i * 2)
+                .ok_or_else(offsets_outside_row)
+                .and_then(|at| u16_at(row, at))
+        })
+        .collect()
+}
+
+// Jet3 only stores the low byte of each offset; a jump table records at which
+// column the offsets pass another 256 byte boundary.
+fn var_offsets_jet3(row: &[u8], bitmask_size: usize) -> io::Result<Vec<u16>> {
+    let last = row.len().checked_sub(1).ok_or_else(offsets_outside_row)?;
+    let count_at = last.checked_sub(bitmask_size).ok_or_else(offsets_outside_row)?;
+    let count = row[count_at] as usize;
+
+    let mut jumps = last / 256;
+    let pointer = count_at.checked_sub(jumps + 1).ok_or_else(offsets_outside_row)?;
+    if pointer.checked_sub(count).ok_or_else(offsets_outside_row)? / 256 < jumps {
+        jumps -= 1;
+    }
+
+    let mut jumps_used = 0;
+    (0..=count)
+        .map(|i| {
+            while jumps_used < jumps && row[count_at - jumps_used - 1] as usize == i {
+                jumps_used += 1;
+            }
+            row.get(pointer - i)
+                .map(|&low| u16::from(low) + jumps_used as u16 * 256)
+                .ok_or_else(offsets_outside_row)
+        })
+        .collect()
+}
+
+fn offsets_outside_row() -> io::Error {
+    invalid("variable column offsets outside row".to_string())
+}
+
+// Jet4 text is UCS-2. Text starting with 0xff 0xfe is "compressed": each
+// byte is a single character until a 0x00 byte toggles to two byte
+// characters and back.
+pub fn decode_ucs2(bytes: &[u8]) -> String {
+    let mut units: Vec<u16> = Vec::with_capacity(bytes.len());
+
+    if bytes.len() >= 2 && bytes[0] == 0xff && bytes[1] == 0xfe {
+        let mut compressed = true;
+        let mut i = 2;
+        while i < bytes.len() {
+            if bytes[i] == 0 {
+                compressed = !compressed;
+                i += 1;
+            } else if compressed {
+                units.push(u16::from(bytes[i]));
+                i += 1;
+            } else if i + 1 < bytes.len() {
+                units.push(u16::from(bytes[i]) | u16::from(bytes[i + 1]) << 8);
+                i += 2;
+            } else {
+                break;
+            }
+        }
+    } else {
+        units.extend(bytes.chunks(2).filter(|c| c.len() == 2).map(|c| u16::from(c[0]) | u16::from(c[1]) << 8));
+    }
+
+    String::from_utf16_lossy(&units)
+}
+
+// Far more days than any date Access shows, but well within what chrono can
+// add to the epoch.
+const MAX_DATETIME_DAYS: f64 = 3_000_000.0;
+
+// Dates are stored as the number of days since 1899-12-30, with the time of
+// day as the fraction.
+fn decode_datetime(days: f64) -> io::Result<NaiveDateTime> {
+    if !days.is_finite() || days.abs() > MAX_DATETIME_DAYS {
+        return Err(invalid(format!("date {} is out of range", days)));
+    }
+    let epoch = NaiveDate::from_ymd_opt(1899, 12, 30).and_then(|date| date.and_hms_opt(0, 0, 0)).expect("valid epoch");
+
+    epoch
+        .checked_add_signed(Duration::milliseconds((days * 86_400_000.0).round() as i64))
+        .ok_or_else(|| invalid(format!("date {} is out of range", days)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_decode_compressed_and_plain_text() {
+        assert_eq!(decode_ucs2(&[0xff, 0xfe, b'K', b'a', b's']), "Kas");
+        assert_eq!(decode_ucs2(&[b'K', 0, b'a', 0, b's', 0]), "Kas");
+        assert_eq!(decode_ucs2(&[0xff, 0xfe, b'a', 0, 0xac, 0x20, 0, b'b']), "a€b");
+    }
+
+    #[test]
+    fn it_should_decode_dates() {
+        let noon = NaiveDate::from_ymd_opt(2019, 1, 2).and_then(|date| date.and_hms_opt(12, 0, 0));
+        assert_eq!(decode_datetime(43467.5).ok(), noon);
+        assert!(decode_datetime(1e300).is_err());
+        assert!(decode_datetime(f64::NAN).is_err());
+    }
+
+    #[test]
+    fn it_should_crack_a_jet4_row() {
+        let db = Database {
+            version: Version::Jet4,
+            layout: &JET4,
+            data: vec![],
+        };
+        let table = Table {
+            db: &db,
+            definition_page: 0,
+            num_var_cols: 1,
+            columns: vec![
+                Column { name: "EN".to_string(), column_type: TYPE_LONG, number: 0, var_index: 0, fixed_offset: 0, size: 4, fixed: true },
+                Column { name: "Name".to_string(), column_type: TYPE_TEXT, number: 1, var_index: 0, fixed_offset: 0, size: 100, fixed: false },
+                Column { name: "Remark".to_string(), column_type: TYPE_TEXT, number: 2, var_index: 1, fixed_offset: 0, size: 100, fixed: false },
+            ],
+            usage_map: 0,
+        };
+
+        let row = vec![
+            3, 0, // column count
+            42, 0, 0, 0, // EN
+            0xff, 0xfe, b'A', b'n', b'n', b'a', // Name
+            12, 0, // end of Name
+            6, 0, // start of Name
+            1, 0, // variable column count
+            0b011, // null mask, Remark is null
+        ];
+
+        let values = table.crack_row(&row).unwrap();
+
+        assert_eq!(values["EN"], Value::Long(42));
+        assert_eq!(values["Name"], Value::Text("Anna".to_string()));
+        assert_eq!(values["Remark"], Value::Null);
+    }
+
+    fn put(buf: &mut [u8], at: usize, value: u32, size: usize) {
+        (0..size).for_each(|i| buf[at + i] = (value >> (8 * i)) as u8);
+    }
+
+    struct FixtureColumn {
+        name: &'static str,
+        column_type: u8,
+        size: u16,
+    }
+
+    impl FixtureColumn {
+        fn fixed(&self) -> bool {
+            self.column_type != TYPE_TEXT
+        }
+    }
+
+    fn column(name: &'static str, column_type: u8, size: u16) -> FixtureColumn {
+        FixtureColumn { name, column_type, size }
+    }
+
+    struct FixtureTable {
+        name: &'static str,
+        columns: Vec<FixtureColumn>,
+        rows: Vec<Vec<Value>>,
+        deleted: Vec<Vec<Value>>,
+    }
+
+    fn text(version: Version, text: &str) -> Vec<u8> {
+        match version {
+            Version::Jet3 => text.as_bytes().to_vec(),
+            Version::Jet4 => [&[0xff, 0xfe], text.as_bytes()].concat(),
+        }
+    }
+
+    // Fixed columns first, then the variable ones, their offsets back to
+    // front and the null mask last.
+    fn encode_row(version: Version, columns: &[FixtureColumn], values: &[Value]) -> Vec<u8> {
+        let width = match version {
+            Version::Jet3 => 1,
+            Version::Jet4 => 2,
+        };
+        let mut row = vec![0; width];
+        put(&mut row, 0, columns.len() as u32, width);
+
+        let mut null_mask = vec![0; columns.len().div_ceil(8)];
+        for (number, (column, value)) in columns.iter().zip(values).enumerate() {
+            if *value != Value::Null {
+                null_mask[number / 8] |= 1 << (number % 8);
+            }
+            if column.fixed() {
+                let mut bytes = vec![0; column.size as usize];
+                match value {
+                    Value::Long(n) => put(&mut bytes, 0, *n as u32, 4),
+                    Value::Int(n) => put(&mut bytes, 0, *n as u16 as u32, 2),
+                    _ => {}
+                }
+                row.extend(bytes);
+            }
+        }
+
+        let mut offsets = vec![];
+        for (_, value) in columns.iter().zip(values).filter(|(column, _)| !column.fixed()) {
+            offsets.push(row.len());
+            if let Value::Text(value) = value {
+                row.extend(text(version, value));
+            }
+        }
+        offsets.push(row.len());
+
+        for offset in offsets.iter().rev() {
+            let at = row.len();
+            row.extend(vec![0; width]);
+            put(&mut row, at, *offset as u32, width);
+        }
+        let at = row.len();
+        row.extend(vec![0; width]);
+        put(&mut row, at, offsets.len() as u32 - 1, width);
+        row.extend(null_mask);
+
+        row
+    }
+
+    fn definition_page(version: Version, layout: &Layout, columns: &[FixtureColumn], usage_map: u32) -> Vec<u8> {
+        let mut page = vec![0; layout.page_size];
+        page[0] = PAGE_TABLE_DEFINITION;
+        page[1] = 0x01;
+        page[layout.num_cols_offset - 5] = 0x4e;
+        put(&mut page, layout.num_cols_offset - 2, columns.iter().filter(|column| !column.fixed()).count() as u32, 2);
+        put(&mut page, layout.num_cols_offset, columns.len() as u32, 2);
+        put(&mut page, layout.usage_map_offset, usage_map, 4);
+
+        let mut offset = layout.columns_offset;
+        let mut fixed_offset = 0;
+        let mut var_index = 0;
+        for (number, column) in columns.iter().enumerate() {
+            let entry = &mut page[offset..offset + layout.column_entry_size];
+            entry[0] = column.column_type;
+            put(entry, layout.column_num_offset, number as u32, 2);
+            put(entry, layout.column_size_offset, u32::from(column.size), 2);
+            if column.fixed() {
+                entry[layout.column_flags_offset] = COLUMN_FIXED;
+                put(entry, layout.column_fixed_offset, fixed_offset, 2);
+                fixed_offset += u32::from(column.size);
+            } else {
+                put(entry, layout.column_var_offset, var_index, 2);
+                var_index += 1;
+            }
+            offset += layout.column_entry_size;
+        }
+
+        for column in columns {
+            let name: Vec<u8> = match version {
+                Version::Jet3 => column.name.bytes().collect(),
+                Version::Jet4 => column.name.bytes().flat_map(|b| vec![b, 0]).collect(),
+            };
+            let width = match version {
+                Version::Jet3 => 1,
+                Version::Jet4 => 2,
+            };
+            put(&mut page, offset, name.len() as u32, width);
+            page[offset + width..offset + width + name.len()].copy_from_slice(&name);
+            offset += width + name.len();
+        }
+        put(&mut page, 8, offset as u32, 4);
+
+        page
+    }
+
+    // Rows are stored from the end of the page to the front.
+    fn data_page(layout: &Layout, owner: u32, rows: &[(u16, Vec<u8>)]) -> Vec<u8> {
+        let mut page = vec![0; layout.page_size];
+        page[0] = PAGE_DATA;
+        page[1] = 0x01;
+        put(&mut page, 4, owner, 4);
+        put(&mut page, layout.row_count_offset, rows.len() as u32, 2);
+
+        let mut end = layout.page_size;
+        for (i, (flags, row)) in rows.iter().enumerate() {
+            let start = end - row.len();
+            page[start..end].copy_from_slice(row);
+            put(&mut page, layout.row_count_offset + 2 + i * 2, start as u32 | u32::from(*flags), 2);
+            end = start;
+        }
+
+        page
+    }
+
+    // A database laid out the way Access does: the header page, the catalog
+    // definition at page 2, a page holding the usage maps, the catalog rows,
+    // then a definition page and a data page per table.
+    fn fixture(version: Version, tables: &[FixtureTable]) -> Vec<u8> {
+        let layout = match version {
+            Version::Jet3 => &JET3,
+            Version::Jet4 => &JET4,
+        };
+        let definition_of = |table: usize| 5 + 2 * table as u32;
+
+        let mut header = vec![0; layout.page_size];
+        header[0..4].copy_from_slice(&[0x00, 0x01, 0x00, 0x00]);
+        header[4..19].copy_from_slice(b"Standard Jet DB");
+        header[0x14] = match version {
+            Version::Jet3 => 0,
+            Version::Jet4 => 1,
+        };
+
+        let catalog_columns = vec![column("Id", TYPE_LONG, 4), column("Name", TYPE_TEXT, 255), column("Type", TYPE_INT, 2)];
+        let mut catalog_rows: Vec<(u16, Vec<u8>)> = tables
+            .iter()
+            .enumerate()
+            .map(|(i, table)| {
+                let values = [Value::Long(definition_of(i) as i32), Value::Text(table.name.to_string()), Value::Int(CATALOG_TYPE_TABLE)];
+                (0, encode_row(version, &catalog_columns, &values))
+            })
+            .collect();
+        // A query sharing the name of a table must not be taken for it
+        let query = [Value::Long(999), Value::Text(tables[0].name.to_string()), Value::Int(5)];
+        catalog_rows.insert(0, (0, encode_row(version, &catalog_columns, &query)));
+
+        let usage_map = |page: u32| {
+            let mut map = vec![0; 6];
+            put(&mut map, 1, page, 4);
+            map[5] = 0x01;
+            (0, map)
+        };
+        let usage_maps: Vec<(u16, Vec<u8>)> = std::iter::once(usage_map(4))
+            .chain((0..tables.len()).map(|i| usage_map(definition_of(i) + 1)))
+            .collect();
+
+        let mut pages = vec![
+            header,
+            vec![0; layout.page_size],
+            definition_page(version, layout, &catalog_columns, 3 << 8),
+            data_page(layout, 0, &usage_maps),
+            data_page(layout, CATALOG_PAGE, &catalog_rows),
+        ];
+        for (i, table) in tables.iter().enumerate() {
+            let rows: Vec<(u16, Vec<u8>)> = table.deleted
+                .iter()
+                .map(|values| (ROW_DELETED, encode_row(version, &table.columns, values)))
+                .chain(table.rows.iter().map(|values| (0, encode_row(version, &table.columns, values))))
+                .collect();
+
+            pages.push(definition_page(version, layout, &table.columns, 3 << 8 | (i as u32 + 1)));
+            pages.push(data_page(layout, definition_of(i), &rows));
+        }
+
+        pages.concat()
+    }
+
+    #[test]
+    fn it_should_read_the_tables_of_a_jet3_and_a_jet4_database() {
+        for &version in &[Version::Jet3, Version::Jet4] {
+            let punches = FixtureTable {
+                name: "Time_RawData",
+                columns: vec![column("TRD_RunNr", TYPE_LONG, 4), column("Remark", TYPE_TEXT, 50), column("Empl", TYPE_LONG, 4), column("Date", TYPE_TEXT, 8)],
+                rows: vec![
+                    vec![Value::Long(173), Value::Null, Value::Long(7), Value::Text("20190102".to_string())],
+                    vec![Value::Long(174), Value::Text("twice".to_string()), Value::Long(8), Value::Text("20190103".to_string())],
+                ],
+                deleted: vec![vec![Value::Long(172), Value::Null, Value::Long(7), Value::Text("20190101".to_string())]],
+            };
+            let employees = FixtureTable {
+                name: "PersonelData",
+                columns: vec![column("EN", TYPE_LONG, 4), column("Name", TYPE_TEXT, 50)],
+                rows: vec![vec![Value::Long(7), Value::Text("Jan".to_string())]],
+                deleted: vec![],
+            };
+
+            let db = Database::from_bytes(fixture(version, &[punches, employees])).unwrap();
+            let rows = db.table("Time_RawData").unwrap().rows().unwrap();
+
+            assert_eq!(db.version(), version);
+            assert_eq!(rows.iter().map(|row| row["TRD_RunNr"].clone()).collect::<Vec<Value>>(), vec![Value::Long(173), Value::Long(174)]);
+            assert_eq!(rows[0]["Remark"], Value::Null);
+            assert_eq!(rows[1]["Remark"], Value::Text("twice".to_string()));
+            assert_eq!(rows[1]["Empl"], Value::Long(8));
+            assert_eq!(rows[1]["Date"], Value::Text("20190103".to_string()));
+            assert_eq!(db.table("PersonelData").unwrap().rows().unwrap()[0]["Name"], Value::Text("Jan".to_string()));
+            assert_eq!(db.table("Actions").err().map(|error| error.kind()), Some(io::ErrorKind::NotFound));
+        }
+    }
+
+    #[test]
+    fn it_should_refuse_corrupt_rows_and_memo_chains() {
+        assert!(var_offsets_jet3(&[], 1).is_err());
+        assert!(var_offsets_jet3(&[0], 1).is_err());
+        assert!(var_offsets_jet3(&[3, 5, 0xff], 1).is_err());
+        assert!(var_offsets_jet4(&[1], 2).is_err());
+
+        // The only part of a memo points back at itself
+        let mut data = vec![0; 2 * JET4.page_size];
+        {
+            let page = &mut data[JET4.page_size..];
+            put(page, JET4.row_count_offset, 1, 2);
+            put(page, JET4.row_count_offset + 2, JET4.page_size as u32 - 4, 2);
+            put(page, JET4.page_size - 4, 1 << 8, 4);
+        }
+        let db = Database { version: Version::Jet4, layout: &JET4, data };
+
+        assert!(db.decode_memo(&[100, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0]).is_err());
+
+        // The second row ends where the first starts, past the page
+        let mut data = vec![0; 2 * JET4.page_size];
+        {
+            let page = &mut data[JET4.page_size..];
+            put(page, JET4.row_count_offset, 2, 2);
+            put(page, JET4.row_count_offset + 2, 5000, 2);
+            put(page, JET4.row_count_offset + 4, 4500, 2);
+        }
+        let db = Database { version: Version::Jet4, layout: &JET4, data };
+
+        assert!(db.pointed_row(1 << 8 | 1).is_err());
+    }
+}
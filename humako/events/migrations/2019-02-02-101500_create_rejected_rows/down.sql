@@ -0,0 +1 @@
+DROP TABLE rejected_rows;
\ No newline at end of file
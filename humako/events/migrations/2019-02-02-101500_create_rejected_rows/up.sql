@@ -0,0 +1,8 @@
+CREATE TABLE rejected_rows (
+  id uuid DEFAULT uuid_generate_v4(),
+  unique_id INTEGER UNIQUE NOT NULL,
+  raw TEXT NOT NULL,
+  reason VARCHAR NOT NULL,
+  timestamp TIMESTAMP DEFAULT Now() NOT NULL,
+  PRIMARY KEY (id)
+);
\ No newline at end of file
@@ -0,0 +1,4 @@
+DELETE FROM events WHERE event_type <> 'time_row_event';
+
+ALTER TABLE events DROP CONSTRAINT events_event_type_unique_id_key;
+ALTER TABLE events ADD CONSTRAINT events_unique_id_key UNIQUE (unique_id);
\ No newline at end of file
@@ -0,0 +1,2 @@
+ALTER TABLE events DROP CONSTRAINT events_unique_id_key;
+ALTER TABLE events ADD CONSTRAINT events_event_type_unique_id_key UNIQUE (event_type, unique_id);
\ No newline at end of file
@@ -0,0 +1 @@
+DROP TABLE import_cursors;
\ No newline at end of file
@@ -0,0 +1,9 @@
+CREATE TABLE import_cursors (
+  source VARCHAR NOT NULL,
+  last_run_nr INTEGER NOT NULL,
+  timestamp TIMESTAMP DEFAULT Now() NOT NULL,
+  PRIMARY KEY (source)
+);
+
+INSERT INTO import_cursors (source, last_run_nr)
+SELECT 'default', MAX(unique_id) FROM events WHERE event_type = 'time_row_event' HAVING COUNT(*) > 0;
\ No newline at end of file
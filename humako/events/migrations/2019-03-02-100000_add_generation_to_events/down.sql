@@ -0,0 +1,4 @@
+ALTER TABLE events DROP CONSTRAINT events_event_type_unique_id_generation_key;
+DELETE FROM events WHERE generation > 0;
+ALTER TABLE events DROP COLUMN generation;
+ALTER TABLE events ADD CONSTRAINT events_event_type_unique_id_key UNIQUE (event_type, unique_id);
\ No newline at end of file
@@ -0,0 +1,3 @@
+ALTER TABLE events ADD COLUMN generation INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE events DROP CONSTRAINT events_event_type_unique_id_key;
+ALTER TABLE events ADD CONSTRAINT events_event_type_unique_id_generation_key UNIQUE (event_type, unique_id, generation);
\ No newline at end of file
@@ -0,0 +1,9 @@
+ALTER TABLE rejected_rows DROP CONSTRAINT rejected_rows_source_unique_id_key;
+DELETE FROM rejected_rows WHERE source <> 'default';
+ALTER TABLE rejected_rows DROP COLUMN source;
+ALTER TABLE rejected_rows ADD CONSTRAINT rejected_rows_unique_id_key UNIQUE (unique_id);
+
+ALTER TABLE events DROP CONSTRAINT events_source_event_type_unique_id_generation_key;
+DELETE FROM events WHERE source <> 'default';
+ALTER TABLE events DROP COLUMN source;
+ALTER TABLE events ADD CONSTRAINT events_event_type_unique_id_generation_key UNIQUE (event_type, unique_id, generation);
\ No newline at end of file
@@ -0,0 +1,7 @@
+ALTER TABLE events ADD COLUMN source VARCHAR NOT NULL DEFAULT 'default';
+ALTER TABLE events DROP CONSTRAINT events_event_type_unique_id_generation_key;
+ALTER TABLE events ADD CONSTRAINT events_source_event_type_unique_id_generation_key UNIQUE (source, event_type, unique_id, generation);
+
+ALTER TABLE rejected_rows ADD COLUMN source VARCHAR NOT NULL DEFAULT 'default';
+ALTER TABLE rejected_rows DROP CONSTRAINT rejected_rows_unique_id_key;
+ALTER TABLE rejected_rows ADD CONSTRAINT rejected_rows_source_unique_id_key UNIQUE (source, unique_id);
\ No newline at end of file
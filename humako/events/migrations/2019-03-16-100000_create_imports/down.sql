@@ -0,0 +1,2 @@
+ALTER TABLE events DROP COLUMN import_id;
+DROP TABLE imports;
\ No newline at end of file
@@ -0,0 +1,20 @@
+CREATE TABLE imports (
+  id UUID PRIMARY KEY,
+  source VARCHAR NOT NULL,
+  filename VARCHAR,
+  sha256 VARCHAR NOT NULL,
+  uploaded_at TIMESTAMP DEFAULT Now() NOT NULL,
+  new_rows INTEGER NOT NULL DEFAULT 0,
+  unchanged_rows INTEGER NOT NULL DEFAULT 0,
+  changed_rows INTEGER NOT NULL DEFAULT 0,
+  rejected_rows INTEGER NOT NULL DEFAULT 0,
+  conflicts INTEGER NOT NULL DEFAULT 0,
+  outcome VARCHAR NOT NULL,
+  report TEXT,
+  error TEXT
+);
+
+-- A file can fail any number of times, but only be imported once per terminal
+CREATE UNIQUE INDEX imports_source_sha256_imported ON imports (source, sha256) WHERE outcome = 'imported';
+
+ALTER TABLE events ADD COLUMN import_id UUID REFERENCES imports (id);
\ No newline at end of file
@@ -0,0 +1,5 @@
+ALTER TABLE imports DROP COLUMN reverted_at;
+ALTER TABLE imports DROP COLUMN previous_last_run_nr;
+
+ALTER TABLE events DROP COLUMN previous_import_id;
+ALTER TABLE events DROP COLUMN previous_payload;
\ No newline at end of file
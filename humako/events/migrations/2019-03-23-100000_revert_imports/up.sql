@@ -0,0 +1,5 @@
+ALTER TABLE events ADD COLUMN previous_payload TEXT;
+ALTER TABLE events ADD COLUMN previous_import_id UUID REFERENCES imports (id);
+
+ALTER TABLE imports ADD COLUMN previous_last_run_nr INTEGER;
+ALTER TABLE imports ADD COLUMN reverted_at TIMESTAMP;
\ No newline at end of file
@@ -0,0 +1 @@
+DROP TABLE staged_imports;
\ No newline at end of file
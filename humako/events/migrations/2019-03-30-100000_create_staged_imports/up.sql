@@ -0,0 +1,9 @@
+CREATE TABLE staged_imports (
+  id UUID PRIMARY KEY,
+  source VARCHAR NOT NULL,
+  filename VARCHAR,
+  sha256 VARCHAR NOT NULL,
+  last_run_nr INTEGER,
+  parsed TEXT NOT NULL,
+  staged_at TIMESTAMP DEFAULT Now() NOT NULL
+);
\ No newline at end of file
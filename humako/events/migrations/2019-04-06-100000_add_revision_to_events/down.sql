@@ -0,0 +1,12 @@
+DELETE FROM events WHERE EXISTS (
+  SELECT 1 FROM events later
+  WHERE later.source = events.source
+    AND later.event_type = events.event_type
+    AND later.unique_id = events.unique_id
+    AND later.generation = events.generation
+    AND later.revision > events.revision
+);
+
+ALTER TABLE events DROP CONSTRAINT events_source_event_type_unique_id_generation_revision_key;
+ALTER TABLE events DROP COLUMN revision;
+ALTER TABLE events ADD CONSTRAINT events_source_event_type_unique_id_generation_key UNIQUE (source, event_type, unique_id, generation);
\ No newline at end of file
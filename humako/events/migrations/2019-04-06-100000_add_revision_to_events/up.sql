@@ -0,0 +1,3 @@
+ALTER TABLE events ADD COLUMN revision INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE events DROP CONSTRAINT events_source_event_type_unique_id_generation_key;
+ALTER TABLE events ADD CONSTRAINT events_source_event_type_unique_id_generation_revision_key UNIQUE (source, event_type, unique_id, generation, revision);
\ No newline at end of file
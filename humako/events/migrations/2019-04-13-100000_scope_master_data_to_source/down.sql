@@ -0,0 +1,2 @@
+UPDATE events SET source = 'default'
+WHERE event_type IN ('employee_registered', 'employee_renamed', 'action_registered', 'action_renamed');
@@ -0,0 +1,4 @@
+UPDATE events SET source = imports.source
+FROM imports
+WHERE events.import_id = imports.id
+  AND events.event_type IN ('employee_registered', 'employee_renamed', 'action_registered', 'action_renamed');
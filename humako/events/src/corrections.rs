@@ -0,0 +1,249 @@
+use std::error::Error;
+use std::fmt;
+
+use chrono::NaiveDateTime;
+use db_parser::{TimeRowEvent, UNKNOWN};
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+
+use crate::models::{Event, NewCorrectionEvent};
+use crate::schema::events;
+use crate::{drop_voided, get_latest_events, DEFAULT_SOURCE, TIME_ROW_EVENT, TIME_ROW_VOIDED};
+
+pub const PUNCH_CORRECTED: &str = "punch_corrected";
+pub const CORRECTION_SOURCE: &str = "correction";
+
+// Refers to a stored punch by its terminal, TRD_RunNr and generation. A punch
+// added by a correction is referred to by source `correction` and the id of
+// that correction.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct PunchRef {
+    pub source: String,
+    pub id: u32,
+    #[serde(default)]
+    pub generation: i32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Correction {
+    // A punch the terminal never registered, like a forgotten clock-in
+    Add {
+        #[serde(default = "default_source")]
+        source: String,
+        employee_id: u32,
+        action_id: u32,
+        timestamp: NaiveDateTime,
+    },
+    Move { punch: PunchRef, timestamp: NaiveDateTime },
+    Retype { punch: PunchRef, action_id: u32 },
+    Void { punch: PunchRef },
+}
+
+fn default_source() -> String {
+    DEFAULT_SOURCE.to_string()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PunchCorrection {
+    pub author: String,
+    pub reason: String,
+    pub correction: Correction,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CorrectionRecord {
+    pub id: i32,
+    pub recorded_at: NaiveDateTime,
+    pub source: String,
+    pub correction: PunchCorrection,
+}
+
+impl CorrectionRecord {
+    fn from_event(event: &Event) -> CorrectionRecord {
+        CorrectionRecord {
+            id: event.unique_id,
+            recorded_at: event.timestamp,
+            source: event.source.clone(),
+            correction: serde_json::from_str(&event.payload).expect("parsing failed"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum CorrectionError {
+    MissingAuthor,
+    MissingReason,
+    UnknownPunch(PunchRef),
+}
+
+impl fmt::Display for CorrectionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CorrectionError::MissingAuthor => write!(f, "a correction needs an author"),
+            CorrectionError::MissingReason => write!(f, "a correction needs a reason"),
+            CorrectionError::UnknownPunch(punch) => {
+                write!(f, "no punch {} of {} (generation {})", punch.id, punch.source, punch.generation)
+            }
+        }
+    }
+}
+
+impl Error for CorrectionError {}
+
+pub fn save_correction(conn: &PgConnection, correction: &PunchCorrection) -> Result<CorrectionRecord, CorrectionError> {
+    if correction.author.trim().is_empty() {
+        return Err(CorrectionError::MissingAuthor);
+    }
+    if correction.reason.trim().is_empty() {
+        return Err(CorrectionError::MissingReason);
+    }
+
+    let source = match &correction.correction {
+        Correction::Add { source, .. } => source.clone(),
+        Correction::Move { punch, .. } | Correction::Retype { punch, .. } | Correction::Void { punch } => {
+            source_of(conn, punch).ok_or_else(|| CorrectionError::UnknownPunch(punch.clone()))?
+        }
+    };
+
+    let event = diesel::insert_into(events::table)
+        .values(&NewCorrectionEvent {
+            id: uuid::Uuid::new_v4(),
+            event_type: PUNCH_CORRECTED,
+            payload: serde_json::to_string(correction).expect("stringify failed"),
+            source: &source,
+        })
+        .get_result::<Event>(conn)
+        .expect("insert failed");
+
+    Ok(CorrectionRecord::from_event(&event))
+}
+
+pub fn get_corrections(conn: &PgConnection) -> Vec<CorrectionRecord> {
+    use crate::schema::events::dsl::*;
+
+    events
+        .filter(event_type.eq(PUNCH_CORRECTED))
+        .order(unique_id.asc())
+        .load::<Event>(conn)
+        .expect("Error loading corrections")
+        .iter()
+        .map(CorrectionRecord::from_event)
+        .collect()
+}
+
+// The terminal of the referred punch, a correction is stored with it so it
+// shows up on worksheets filtered on that terminal.
+fn source_of(conn: &PgConnection, punch: &PunchRef) -> Option<String> {
+    if punch.source == CORRECTION_SOURCE {
+        return events::table
+            .filter(events::event_type.eq(PUNCH_CORRECTED))
+            .filter(events::unique_id.eq(punch.id as i32))
+            .first::<Event>(conn)
+            .optional()
+            .expect("Error loading corrections")
+            .filter(|event| matches!(CorrectionRecord::from_event(event).correction.correction, Correction::Add { .. }))
+            .map(|event| event.source);
+    }
+
+    let stored: Vec<Event> = get_latest_events(conn, &punch.source, TIME_ROW_EVENT, vec![punch.id as i32])
+        .into_iter()
+        .chain(get_latest_events(conn, &punch.source, TIME_ROW_VOIDED, vec![punch.id as i32]))
+        .map(|(_, event)| event)
+        .collect();
+
+    drop_voided(stored)
+        .into_iter()
+        .find(|event| event.event_type == TIME_ROW_EVENT && event.generation == punch.generation)
+        .map(|event| event.source)
+}
+
+// The punches with the corrections applied in the order they were made, with
+// the terminal they were punched at. The events should hold only the latest
+// revisions, without voided punches.
+pub fn corrected_time_rows(list_of_events: &[Event]) -> Vec<(String, TimeRowEvent)> {
+    let mut punches: Vec<(PunchRef, String, TimeRowEvent)> = list_of_events
+        .iter()
+        .filter(|event| event.event_type == TIME_ROW_EVENT)
+        .map(|event| {
+            let punch = PunchRef { source: event.source.clone(), id: event.unique_id as u32, generation: event.generation };
+
+            (punch, event.source.clone(), serde_json::from_str(&event.payload).expect("parsing failed"))
+        })
+        .collect();
+
+    let mut corrections: Vec<&Event> = list_of_events
+        .iter()
+        .filter(|event| event.event_type == PUNCH_CORRECTED)
+        .collect();
+    corrections.sort_by_key(|event| event.unique_id);
+
+    for event in corrections {
+        let correction: PunchCorrection = serde_json::from_str(&event.payload).expect("parsing failed");
+
+        match correction.correction {
+            Correction::Add { employee_id, action_id, timestamp, .. } => punches.push((
+                PunchRef { source: CORRECTION_SOURCE.to_string(), id: event.unique_id as u32, generation: 0 },
+                event.source.clone(),
+                TimeRowEvent {
+                    id: event.unique_id as u32,
+                    employee_id: Some(employee_id),
+                    employee: UNKNOWN.to_string(),
+                    action_id: Some(action_id),
+                    action: UNKNOWN.to_string(),
+                    timestamp,
+                },
+            )),
+            Correction::Move { punch, timestamp } => punches
+                .iter_mut()
+                .filter(|(key, _, _)| *key == punch)
+                .for_each(|(_, _, time_row)| time_row.timestamp = timestamp),
+            Correction::Retype { punch, action_id } => punches
+                .iter_mut()
+                .filter(|(key, _, _)| *key == punch)
+                .for_each(|(_, _, time_row)| {
+                    time_row.action_id = Some(action_id);
+                    time_row.action = UNKNOWN.to_string();
+                }),
+            Correction::Void { punch } => punches.retain(|(key, _, _)| *key != punch),
+        }
+    }
+
+    punches.into_iter().map(|(_, terminal, time_row)| (terminal, time_row)).collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDateTime;
+    use crate::models::Event;
+    use crate::TIME_ROW_EVENT;
+    use super::*;
+
+    fn event(event_type: &str, unique_id: i32, payload: &str) -> Event {
+        Event::new(event_type, "default", unique_id, payload.to_string())
+    }
+
+    fn correction(unique_id: i32, correction: &str) -> Event {
+        event(PUNCH_CORRECTED, unique_id, &format!("{{\"author\":\"Michel\",\"reason\":\"Vergeten\",\"correction\":{}}}", correction))
+    }
+
+    #[test]
+    fn it_should_apply_corrections_in_order() {
+        let events = vec![
+            event(TIME_ROW_EVENT, 1, "{\"id\":1,\"employee_id\":7,\"employee\":\"Jan\",\"action_id\":2,\"action\":\"Kas\",\"timestamp\":\"2019-01-02T08:00:00\"}"),
+            event(TIME_ROW_EVENT, 2, "{\"id\":2,\"employee_id\":7,\"employee\":\"Jan\",\"action_id\":2,\"action\":\"Kas\",\"timestamp\":\"2019-01-02T09:00:00\"}"),
+            correction(11, "{\"kind\":\"move\",\"punch\":{\"source\":\"default\",\"id\":1},\"timestamp\":\"2019-01-02T08:30:00\"}"),
+            correction(10, "{\"kind\":\"add\",\"employee_id\":7,\"action_id\":1,\"timestamp\":\"2019-01-02T07:00:00\"}"),
+            correction(12, "{\"kind\":\"retype\",\"punch\":{\"source\":\"correction\",\"id\":10},\"action_id\":3}"),
+            correction(13, "{\"kind\":\"void\",\"punch\":{\"source\":\"default\",\"id\":2}}"),
+        ];
+
+        let time_rows = corrected_time_rows(&events);
+
+        assert_eq!(time_rows.len(), 2);
+        assert_eq!(time_rows[0].1.timestamp, NaiveDateTime::parse_from_str("2019-01-02T08:30:00", "%Y-%m-%dT%H:%M:%S").unwrap());
+        assert_eq!(time_rows[1].1.id, 10);
+        assert_eq!(time_rows[1].1.action_id, Some(3));
+    }
+}
@@ -0,0 +1,445 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use db_parser::{ParsedDb, RejectedRow, TimeRowEvent};
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use sha2::{Digest, Sha256};
+
+use crate::models::{Event, ImportCursor, ImportRecord, NewImportCursor, NewImportRecord};
+use crate::schema::{import_cursors, imports};
+use crate::{drop_voided, latest_revisions, save_punches, save_master_data, save_rejected_rows, Punch, ROWS_PER_QUERY, TIME_ROW_EVENT, TIME_ROW_VOIDED};
+
+pub const DEFAULT_SOURCE: &str = "default";
+
+pub const IMPORTED: &str = "imported";
+pub const FAILED: &str = "failed";
+
+// An uploaded file, identified by its contents.
+#[derive(Debug)]
+pub struct Upload<'a> {
+    pub source: &'a str,
+    pub filename: Option<&'a str>,
+    pub sha256: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ImportReport {
+    pub import_id: uuid::Uuid,
+    // Set when the same file was imported before and nothing was done
+    #[serde(default)]
+    pub duplicate: bool,
+    pub source: String,
+    pub new: usize,
+    pub unchanged: usize,
+    pub changed: usize,
+    pub rejected: usize,
+    pub last_run_nr: Option<u32>,
+    // The highest TRD_RunNr in the file itself, where the terminal's counter
+    // stood. The cursor never goes back, so a reset is told by this instead.
+    #[serde(default)]
+    pub highest_in_file: Option<u32>,
+    pub counter_reset: bool,
+    pub conflicts: Vec<Conflict>,
+    pub rejected_rows: Vec<RejectedRow>,
+}
+
+// A row that reuses the TRD_RunNr of a punch by another employee or at
+// another moment. It is stored next to the existing punches under a new
+// generation.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Conflict {
+    pub run_nr: u32,
+    pub generation: i32,
+    pub existing: Vec<TimeRowEvent>,
+    pub incoming: TimeRowEvent,
+}
+
+// A stored punch that only gets another action.
+#[derive(Debug, Clone, Serialize)]
+pub struct Change {
+    pub previous: TimeRowEvent,
+    pub punch: Punch,
+}
+
+#[derive(Debug, Default)]
+pub struct ImportPlan {
+    pub new: Vec<Punch>,
+    pub changed: Vec<Change>,
+    pub unchanged: usize,
+    pub conflicts: Vec<Conflict>,
+}
+
+pub fn get_last_run_nr(conn: &PgConnection, source: &str) -> Option<u32> {
+    import_cursors::table
+        .find(source)
+        .first::<ImportCursor>(conn)
+        .optional()
+        .expect("Error loading import cursor")
+        .map(|cursor| cursor.last_run_nr as u32)
+}
+
+pub(crate) fn set_last_run_nr(conn: &PgConnection, source: &str, last_run_nr: u32) {
+    diesel::insert_into(import_cursors::table)
+        .values(&NewImportCursor { source, last_run_nr: last_run_nr as i32 })
+        .on_conflict(import_cursors::source)
+        .do_update()
+        .set((
+            import_cursors::last_run_nr.eq(last_run_nr as i32),
+            import_cursors::timestamp.eq(diesel::dsl::now),
+        ))
+        .execute(conn)
+        .expect("insert failed");
+}
+
+pub fn file_sha256(path: &Path) -> io::Result<String> {
+    let bytes = fs::read(path)?;
+
+    Ok(format!("{:x}", Sha256::digest(&bytes)))
+}
+
+// The report of an earlier import of the same file from the same terminal.
+pub fn find_import(conn: &PgConnection, upload: &Upload) -> Option<ImportReport> {
+    imports::table
+        .filter(imports::source.eq(upload.source))
+        .filter(imports::sha256.eq(&upload.sha256))
+        .filter(imports::outcome.eq(IMPORTED))
+        .first::<ImportRecord>(conn)
+        .optional()
+        .expect("Error loading imports")
+        .and_then(|record| record.report)
+        .map(|report| {
+            let mut report: ImportReport = serde_json::from_str(&report).expect("parsing failed");
+            report.duplicate = true;
+
+            report
+        })
+}
+
+pub fn get_imports(conn: &PgConnection) -> Vec<ImportRecord> {
+    imports::table
+        .order(imports::uploaded_at.desc())
+        .load::<ImportRecord>(conn)
+        .expect("Error loading imports")
+}
+
+pub fn record_failed_import(conn: &PgConnection, upload: &Upload, error: &str) -> uuid::Uuid {
+    let id = uuid::Uuid::new_v4();
+
+    diesel::insert_into(imports::table)
+        .values(&NewImportRecord {
+            id,
+            source: upload.source,
+            filename: upload.filename,
+            sha256: &upload.sha256,
+            new_rows: 0,
+            unchanged_rows: 0,
+            changed_rows: 0,
+            rejected_rows: 0,
+            conflicts: 0,
+            outcome: FAILED,
+            report: None,
+            error: Some(error),
+            previous_last_run_nr: None,
+        })
+        .execute(conn)
+        .expect("insert failed");
+
+    id
+}
+
+fn record_import(conn: &PgConnection, upload: &Upload, report: &ImportReport, previous_last_run_nr: Option<u32>) {
+    diesel::insert_into(imports::table)
+        .values(&NewImportRecord {
+            id: report.import_id,
+            source: upload.source,
+            filename: upload.filename,
+            sha256: &upload.sha256,
+            new_rows: report.new as i32,
+            unchanged_rows: report.unchanged as i32,
+            changed_rows: report.changed as i32,
+            rejected_rows: report.rejected as i32,
+            conflicts: report.conflicts.len() as i32,
+            outcome: IMPORTED,
+            report: Some(serde_json::to_string(report).expect("stringify failed")),
+            error: None,
+            previous_last_run_nr: previous_last_run_nr.map(|run_nr| run_nr as i32),
+        })
+        .execute(conn)
+        .expect("insert failed");
+}
+
+// Where the terminal's counter stood at the last import from it, reports from
+// before that was kept only have the cursor.
+fn get_last_counter(conn: &PgConnection, source: &str) -> Option<u32> {
+    imports::table
+        .filter(imports::source.eq(source))
+        .filter(imports::outcome.eq(IMPORTED))
+        .order(imports::uploaded_at.desc())
+        .first::<ImportRecord>(conn)
+        .optional()
+        .expect("Error loading imports")
+        .and_then(|record| record.report)
+        .and_then(|report| {
+            let report: ImportReport = serde_json::from_str(&report).expect("parsing failed");
+
+            report.highest_in_file.or(report.last_run_nr)
+        })
+}
+
+// The terminal never forgets a TRD_RunNr on its own, a file that stops short
+// of the previous one comes from a reset or replaced terminal. Only the first
+// file after the reset does, so it is reported once.
+fn is_counter_reset(highest_in_file: Option<u32>, last_counter: Option<u32>) -> bool {
+    match (highest_in_file, last_counter) {
+        (Some(in_file), Some(last)) => in_file < last,
+        _ => false,
+    }
+}
+
+// Every terminal that has been imported from.
+pub fn get_sources(conn: &PgConnection) -> Vec<String> {
+    import_cursors::table
+        .select(import_cursors::source)
+        .order(import_cursors::source.asc())
+        .load::<String>(conn)
+        .expect("Error loading sources")
+}
+
+// Only loads the stored punches with the given TRD_RunNrs.
+fn get_punches(conn: &PgConnection, terminal: &str, run_nrs: &[i32]) -> HashMap<u32, Vec<Punch>> {
+    use crate::schema::events::dsl::*;
+
+    let loaded = events
+        .filter(source.eq(terminal))
+        .filter(event_type.eq_any(vec![TIME_ROW_EVENT, TIME_ROW_VOIDED]))
+        .filter(unique_id.eq_any(run_nrs))
+        .load::<Event>(conn)
+        .expect("Error loading events");
+
+    drop_voided(latest_revisions(loaded))
+        .iter()
+        .fold(HashMap::new(), |mut map, event| {
+            let time_row: TimeRowEvent = serde_json::from_str(&event.payload).expect("parsing failed");
+            map.entry(time_row.id)
+                .or_insert(vec![])
+                .push(Punch { generation: event.generation, time_row });
+
+            map
+        })
+}
+
+// Rows up to the cursor were seen before and are compared against what is
+// stored, rows past it against the rows earlier in the same file. A row for
+// the same employee and moment can only change its action; anything else
+// reusing a TRD_RunNr is a conflict and never overwrites a stored punch.
+pub fn plan_import(stored: HashMap<u32, Vec<Punch>>, time_rows: Vec<TimeRowEvent>, imported_rows: Vec<TimeRowEvent>) -> ImportPlan {
+    let mut known = stored;
+    let mut plan = ImportPlan::default();
+
+    imported_rows
+        .into_iter()
+        .chain(time_rows)
+        .for_each(|time_row| {
+            let punches = known.entry(time_row.id).or_insert(vec![]);
+
+            match punches.iter().position(|punch| punch.time_row.same_moment(&time_row)) {
+                Some(index) if punches[index].time_row.same_punch(&time_row) => plan.unchanged += 1,
+                Some(index) => {
+                    let previous = punches[index].time_row.clone();
+                    punches[index].time_row = time_row;
+                    plan.changed.push(Change { previous, punch: punches[index].clone() });
+                }
+                None if punches.is_empty() => {
+                    let punch = Punch { generation: 0, time_row };
+                    punches.push(punch.clone());
+                    plan.new.push(punch);
+                }
+                None => {
+                    let generation = punches.iter().map(|punch| punch.generation).max().unwrap_or(0) + 1;
+                    plan.conflicts.push(Conflict {
+                        run_nr: time_row.id,
+                        generation,
+                        existing: punches.iter().map(|punch| punch.time_row.clone()).collect(),
+                        incoming: time_row.clone(),
+                    });
+
+                    let punch = Punch { generation, time_row };
+                    punches.push(punch.clone());
+                    plan.new.push(punch);
+                }
+            }
+        });
+
+    plan
+}
+
+// Every row up to the cursor the file still holds is loaded and compared, so
+// an upload is O(stored rows) for its terminal and gets slower over a season.
+// Only the queries are bounded, by looking the rows up a chunk of ids at a
+// time.
+pub fn plan_stored_import(conn: &PgConnection, source: &str, time_rows: Vec<TimeRowEvent>, imported_rows: Vec<TimeRowEvent>) -> ImportPlan {
+    let mut ids: Vec<i32> = imported_rows.iter().map(|time_row| time_row.id as i32).collect();
+    ids.sort();
+    ids.dedup();
+
+    let stored = ids
+        .chunks(ROWS_PER_QUERY)
+        .fold(HashMap::new(), |mut stored, chunk| {
+            stored.extend(get_punches(conn, source, chunk));
+
+            stored
+        });
+
+    plan_import(stored, time_rows, imported_rows)
+}
+
+// Importing a file that was imported before returns the earlier report
+// without touching any events.
+pub fn import(conn: &PgConnection, upload: &Upload, parsed: ParsedDb) -> ImportReport {
+    conn.transaction::<_, diesel::result::Error, _>(|| {
+        if let Some(report) = find_import(conn, upload) {
+            return Ok(report);
+        }
+
+        let source = upload.source;
+        let import_id = uuid::Uuid::new_v4();
+        let last_run_nr = get_last_run_nr(conn, source);
+
+        let highest_in_file = parsed.time_rows
+            .iter()
+            .chain(parsed.imported_rows.iter())
+            .map(|time_row| time_row.id)
+            .max();
+        let highest = highest_in_file.into_iter().chain(last_run_nr).max();
+        let counter_reset = is_counter_reset(highest_in_file, get_last_counter(conn, source).or(last_run_nr));
+
+        let plan = plan_stored_import(conn, source, parsed.time_rows, parsed.imported_rows);
+        let new = plan.new.len();
+        let changed = plan.changed.len();
+
+        // The batch has to exist before the events can refer to it
+        let report = ImportReport {
+            import_id,
+            duplicate: false,
+            source: source.to_string(),
+            new,
+            unchanged: plan.unchanged,
+            changed,
+            rejected: parsed.rejected_rows.len(),
+            last_run_nr: highest,
+            highest_in_file,
+            counter_reset,
+            conflicts: plan.conflicts,
+            rejected_rows: parsed.rejected_rows,
+        };
+        record_import(conn, upload, &report, last_run_nr);
+
+        let mut to_save = plan.new;
+        to_save.extend(plan.changed.into_iter().map(|change| change.punch));
+        if !to_save.is_empty() {
+            save_punches(conn, source, to_save, Some(import_id));
+        }
+        save_rejected_rows(conn, source, &report.rejected_rows);
+        save_master_data(conn, source, &parsed.master_data, Some(import_id));
+
+        if let Some(highest) = highest {
+            set_last_run_nr(conn, source, highest);
+        }
+
+        Ok(report)
+    }).expect("import failed")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use db_parser::TimeRowEvent;
+    use crate::Punch;
+    use super::{is_counter_reset, plan_import, Conflict, ImportReport};
+
+    fn time_row(id: u32, employee_id: u32, action_id: u32, time: &str) -> TimeRowEvent {
+        serde_json::from_str(&format!(
+            "{{\"id\":{},\"employee_id\":{},\"employee\":\"Michel\",\"action_id\":{},\"action\":\"Kas\",\"timestamp\":\"2019-01-02T{}\"}}",
+            id, employee_id, action_id, time
+        )).unwrap()
+    }
+
+    fn stored(punches: Vec<Punch>) -> HashMap<u32, Vec<Punch>> {
+        punches.into_iter().fold(HashMap::new(), |mut map, punch| {
+            map.entry(punch.time_row.id).or_insert(vec![]).push(punch);
+            map
+        })
+    }
+
+    fn ids(punches: &[Punch]) -> Vec<(u32, i32)> {
+        punches.iter().map(|punch| (punch.time_row.id, punch.generation)).collect()
+    }
+
+    #[test]
+    fn it_should_separate_new_unchanged_and_changed_rows() {
+        let stored = stored(vec![
+            Punch { generation: 0, time_row: time_row(1, 7, 2, "07:00:00") },
+            Punch { generation: 0, time_row: time_row(2, 7, 2, "08:00:00") },
+        ]);
+
+        let plan = plan_import(
+            stored,
+            vec![time_row(4, 7, 2, "10:00:00")],
+            vec![time_row(1, 7, 2, "07:00:00"), time_row(2, 7, 3, "08:00:00"), time_row(3, 7, 2, "09:00:00")],
+        );
+
+        assert_eq!(ids(&plan.new), vec![(3, 0), (4, 0)]);
+        assert_eq!(plan.changed.iter().map(|change| (change.previous.action_id, change.punch.time_row.action_id)).collect::<Vec<_>>(), vec![(Some(2), Some(3))]);
+        assert_eq!(plan.unchanged, 1);
+        assert!(plan.conflicts.is_empty());
+    }
+
+    #[test]
+    fn it_should_never_overwrite_a_punch_with_a_reused_run_nr() {
+        let stored = stored(vec![
+            Punch { generation: 0, time_row: time_row(1, 7, 2, "07:00:00") },
+        ]);
+
+        let plan = plan_import(
+            stored,
+            vec![],
+            vec![time_row(1, 8, 2, "07:00:00"), time_row(1, 7, 2, "09:00:00")],
+        );
+
+        assert_eq!(ids(&plan.new), vec![(1, 1), (1, 2)]);
+        assert!(plan.changed.is_empty());
+        assert_eq!(plan.conflicts.iter().map(|conflict| conflict.generation).collect::<Vec<i32>>(), vec![1, 2]);
+        assert_eq!(plan.conflicts[1].existing.len(), 2);
+    }
+
+    #[test]
+    fn it_should_report_a_counter_reset_once() {
+        let cursor = Some(1000);
+
+        let first = is_counter_reset(Some(50), cursor);
+        let next = is_counter_reset(Some(60), Some(50));
+
+        assert!(first);
+        assert!(!next);
+        assert!(!is_counter_reset(Some(1010), cursor));
+    }
+
+    #[test]
+    fn it_should_restore_a_stored_report() {
+        let report = ImportReport {
+            new: 1,
+            conflicts: vec![Conflict { run_nr: 1, generation: 1, existing: vec![time_row(1, 7, 2, "07:00:00")], incoming: time_row(1, 8, 2, "07:00:00") }],
+            ..ImportReport::default()
+        };
+
+        let stored: ImportReport = serde_json::from_str(&serde_json::to_string(&report).unwrap()).unwrap();
+
+        assert_eq!(stored.new, 1);
+        assert_eq!(stored.conflicts[0].incoming.employee_id, Some(8));
+        assert!(!stored.duplicate);
+    }
+}
@@ -6,9 +6,15 @@ extern crate dotenv;
 
 pub mod schema;
 pub mod models;
+pub mod master_data;
+pub mod import;
+pub mod revert;
+pub mod staging;
+pub mod corrections;
 
 use diesel::pg::PgConnection;
 use dotenv::dotenv;
+use std::collections::{HashMap, HashSet};
 use std::env;
 
 pub fn establish_connection() -> PgConnection {
@@ -21,9 +27,21 @@ pub fn establish_connection() -> PgConnection {
 }
 
 use self::schema::events;
+use self::schema::rejected_rows;
 use self::models::*;
 use self::diesel::prelude::*;
-use db_parser::TimeRowEvent;
+use chrono::NaiveDateTime;
+use db_parser::{RejectedRow, TimeRowEvent};
+
+pub use self::master_data::{get_master_data, get_master_data_as_of, get_master_data_events, save_master_data};
+pub use self::import::{file_sha256, find_import, get_imports, get_last_run_nr, get_sources, import, record_failed_import, ImportReport, Upload, DEFAULT_SOURCE};
+
+pub use self::revert::{revert_import, RevertError, RevertReport};
+pub use self::corrections::{corrected_time_rows, get_corrections, save_correction, Correction, CorrectionError, CorrectionRecord, PunchCorrection, PunchRef, PUNCH_CORRECTED};
+pub use self::staging::{commit_staged, discard_staged, get_staged_imports, preview_staged, stage_import, ImportDiff, Preview, StagingError};
+
+pub const TIME_ROW_EVENT: &str = "time_row_event";
+pub const TIME_ROW_VOIDED: &str = "time_row_voided";
 
 pub fn print_events() {
     use self::schema::events::dsl::*;
@@ -41,6 +59,17 @@ pub fn print_events() {
     }
 }
 
+pub fn get_source_events(conn: &PgConnection, terminal: &str) -> Vec<Event> {
+    use self::schema::events::dsl::*;
+
+    let results = events
+        .filter(source.eq(terminal))
+        .load::<Event>(conn)
+        .expect("Error loading events");
+
+    latest_revisions(results)
+}
+
 pub fn get_events(conn: &PgConnection) -> Vec<Event> {
     use self::schema::events::dsl::*;
 
@@ -48,7 +77,153 @@ pub fn get_events(conn: &PgConnection) -> Vec<Event> {
         .load::<Event>(conn)
         .expect("Error loading events");
 
+    latest_revisions(results)
+}
+
+// The events as they were known at the given moment.
+pub fn get_events_as_of(conn: &PgConnection, moment: NaiveDateTime) -> Vec<Event> {
+    use self::schema::events::dsl::*;
+
+    let results = events
+        .filter(timestamp.le(moment))
+        .load::<Event>(conn)
+        .expect("Error loading events");
+
+    latest_revisions(results)
+}
+
+// Every revision of every event, for deriving what was known at an earlier
+// moment.
+pub fn get_event_history(conn: &PgConnection, terminal: Option<&str>) -> Vec<Event> {
+    use self::schema::events::dsl::*;
+
+    match terminal {
+        Some(terminal) => events.filter(source.eq(terminal)).load::<Event>(conn),
+        None => events.load::<Event>(conn),
+    }.expect("Error loading events")
+}
+
+// Every revision of an event is kept, only the latest one counts.
+pub fn latest_revisions(list_of_events: Vec<Event>) -> Vec<Event> {
+    let latest: HashMap<(String, String, i32, i32), i32> = list_of_events
+        .iter()
+        .fold(HashMap::new(), |mut map, event| {
+            let revision = map
+                .entry((event.source.clone(), event.event_type.clone(), event.unique_id, event.generation))
+                .or_insert(event.revision);
+            *revision = (*revision).max(event.revision);
+
+            map
+        });
+
+    list_of_events
+        .into_iter()
+        .filter(|event| latest[&(event.source.clone(), event.event_type.clone(), event.unique_id, event.generation)] == event.revision)
+        .collect()
+}
+
+// Postgres takes at most 65535 parameters per query, a season of punches is
+// loaded and stored this many at a time.
+pub(crate) const ROWS_PER_QUERY: usize = 5_000;
+
+// The latest revision of the given events of a terminal, keyed on their
+// TRD_RunNr and generation.
+pub(crate) fn get_latest_events(conn: &PgConnection, terminal: &str, kind: &str, ids: Vec<i32>) -> HashMap<(i32, i32), Event> {
+    latest_revisions(load_events(conn, terminal, kind, &ids))
+        .into_iter()
+        .map(|event| ((event.unique_id, event.generation), event))
+        .collect()
+}
+
+// Every revision of the given events of a terminal, oldest first.
+pub(crate) fn get_revisions(conn: &PgConnection, terminal: &str, kind: &str, ids: Vec<i32>) -> HashMap<(i32, i32), Vec<Event>> {
+    let mut results = load_events(conn, terminal, kind, &ids);
+    results.sort_by_key(|event| event.revision);
+
     results
+        .into_iter()
+        .fold(HashMap::new(), |mut history, event| {
+            history.entry((event.unique_id, event.generation)).or_insert_with(Vec::new).push(event);
+
+            history
+        })
+}
+
+fn load_events(conn: &PgConnection, terminal: &str, kind: &str, ids: &[i32]) -> Vec<Event> {
+    use self::schema::events::dsl::*;
+
+    ids
+        .chunks(ROWS_PER_QUERY)
+        .flat_map(|chunk| {
+            events
+                .filter(source.eq(terminal))
+                .filter(event_type.eq(kind))
+                .filter(unique_id.eq_any(chunk))
+                .load::<Event>(conn)
+                .expect("Error loading events")
+        })
+        .collect()
+}
+
+// Stores an event as the next revision of what is stored under the same
+// key, keeping the payload it replaces.
+pub(crate) fn new_revision<'a>(
+    event_type: &'a str,
+    source: &'a str,
+    unique_id: i32,
+    generation: i32,
+    payload: String,
+    import_id: Option<uuid::Uuid>,
+    previous: Option<&Event>,
+) -> NewEvent<'a> {
+    NewEvent {
+        id: uuid::Uuid::new_v4(),
+        unique_id,
+        event_type,
+        payload,
+        generation,
+        source,
+        import_id,
+        revision: previous.map(|event| event.revision + 1).unwrap_or(0),
+        previous_payload: previous.map(|event| event.payload.clone()),
+        previous_import_id: previous.and_then(|event| event.import_id),
+    }
+}
+
+// A time row together with the generation that tells apart punches which
+// reused a TRD_RunNr after the terminal's counter was reset.
+#[derive(Debug, Clone, Serialize)]
+pub struct Punch {
+    pub generation: i32,
+    pub time_row: TimeRowEvent,
+}
+
+// Compensates a punch stored by an import that was reverted. It shares the
+// source, TRD_RunNr and generation of the punch it voids.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VoidedPunch {
+    pub id: u32,
+    pub generation: i32,
+    pub import_id: uuid::Uuid,
+}
+
+// A punch stays voided for as long as it holds what the reverted import
+// stored, importing it again brings it back.
+pub fn drop_voided(list_of_events: Vec<Event>) -> Vec<Event> {
+    let voided: HashSet<(String, i32, i32, Option<uuid::Uuid>)> = list_of_events
+        .iter()
+        .filter(|event| event.event_type == TIME_ROW_VOIDED)
+        .map(|event| (event.source.clone(), event.unique_id, event.generation, event.import_id))
+        .collect();
+
+    list_of_events
+        .into_iter()
+        .filter(|event| match event.event_type.as_str() {
+            TIME_ROW_VOIDED => false,
+            TIME_ROW_EVENT => !voided.contains(&(event.source.clone(), event.unique_id, event.generation, event.import_id)),
+            _ => true,
+        })
+        .collect()
 }
 
 pub fn save_event(conn: &PgConnection, event: TimeRowEvent) {
@@ -56,26 +231,99 @@ pub fn save_event(conn: &PgConnection, event: TimeRowEvent) {
 }
 
 pub fn save_events(conn: &PgConnection, list_of_events: Vec<TimeRowEvent>) {
-    let mut result = vec![];
-
-    list_of_events.iter().for_each(|event| {
-        let my_uuid = uuid::Uuid::new_v4();
-        let payload: String = event.stringify();
-
-        let event = NewEvent {
-            id: my_uuid,
-            unique_id: event.id as i32,
-            event_type: "time_row_event",
-            payload: payload,
-        };
-        result.push(event);
+    let punches = list_of_events
+        .into_iter()
+        .map(|time_row| Punch { generation: 0, time_row })
+        .collect();
+
+    save_punches(conn, DEFAULT_SOURCE, punches, None);
+}
+
+// Stored punches are never overwritten, a punch that was stored before gets
+// a new revision.
+pub fn save_punches(conn: &PgConnection, source: &str, punches: Vec<Punch>, import_id: Option<uuid::Uuid>) {
+    let ids = punches.iter().map(|punch| punch.time_row.id as i32).collect();
+    let latest = get_latest_events(conn, source, TIME_ROW_EVENT, ids);
+
+    let result: Vec<NewEvent> = punches
+        .iter()
+        .map(|punch| {
+            let unique_id = punch.time_row.id as i32;
+
+            new_revision(
+                TIME_ROW_EVENT,
+                source,
+                unique_id,
+                punch.generation,
+                punch.time_row.stringify(),
+                import_id,
+                latest.get(&(unique_id, punch.generation)),
+            )
+        })
+        .collect();
+
+    result.chunks(ROWS_PER_QUERY).for_each(|chunk| {
+        diesel::insert_into(events::table)
+            .values(chunk)
+            .execute(conn)
+            .expect("insert failed");
     });
+}
+
+pub fn save_rejected_rows(conn: &PgConnection, source: &str, list_of_rows: &[RejectedRow]) {
+    if list_of_rows.is_empty() {
+        return;
+    }
 
-    diesel::insert_into(events::table)
+    let result: Vec<NewRejectedRow> = list_of_rows
+        .iter()
+        .map(|row| NewRejectedRow {
+            id: uuid::Uuid::new_v4(),
+            unique_id: row.id as i32,
+            raw: serde_json::to_string(&row.raw).expect("stringify failed"),
+            reason: &row.reason,
+            source,
+        })
+        .collect();
+
+    diesel::insert_into(rejected_rows::table)
         .values(&result)
-        .on_conflict(events::unique_id)
+        .on_conflict((rejected_rows::source, rejected_rows::unique_id))
         .do_update()
-        .set(events::payload.eq(diesel::pg::upsert::excluded(events::payload)))
+        .set((
+            rejected_rows::raw.eq(diesel::pg::upsert::excluded(rejected_rows::raw)),
+            rejected_rows::reason.eq(diesel::pg::upsert::excluded(rejected_rows::reason)),
+        ))
         .execute(conn)
         .expect("insert failed");
-}
\ No newline at end of file
+}
+
+pub fn get_rejected_rows(conn: &PgConnection) -> Vec<RejectedRowRecord> {
+    use self::schema::rejected_rows::dsl::*;
+
+    rejected_rows
+        .load::<RejectedRowRecord>(conn)
+        .expect("Error loading rejected rows")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::Event;
+    use crate::{latest_revisions, TIME_ROW_EVENT};
+
+    fn revision(unique_id: i32, revision: i32, payload: &str) -> Event {
+        Event { revision, ..Event::new(TIME_ROW_EVENT, "default", unique_id, payload.to_string()) }
+    }
+
+    #[test]
+    fn it_should_only_keep_the_latest_revision() {
+        let events = latest_revisions(vec![
+            revision(1, 0, "first"),
+            revision(2, 0, "other"),
+            revision(1, 2, "third"),
+            revision(1, 1, "second"),
+        ]);
+
+        assert_eq!(events.iter().map(|event| event.payload.as_str()).collect::<Vec<&str>>(), vec!["other", "third"]);
+    }
+}
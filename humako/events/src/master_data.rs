@@ -0,0 +1,219 @@
+use std::collections::HashMap;
+
+use chrono::NaiveDateTime;
+use db_parser::{MasterData, MasterDataPerSource};
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+
+use crate::models::{Event, NewMasterDataEvent};
+use crate::schema::events;
+
+pub const EMPLOYEE_REGISTERED: &str = "employee_registered";
+pub const EMPLOYEE_RENAMED: &str = "employee_renamed";
+pub const ACTION_REGISTERED: &str = "action_registered";
+pub const ACTION_RENAMED: &str = "action_renamed";
+
+const MASTER_DATA_EVENTS: [&str; 4] = [EMPLOYEE_REGISTERED, EMPLOYEE_RENAMED, ACTION_REGISTERED, ACTION_RENAMED];
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct MasterDataChange {
+    pub id: u32,
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous_name: Option<String>,
+}
+
+// Only emits events for what is new or renamed compared to what is already
+// known of the terminal, so importing the same master data twice leaves no
+// trace.
+pub fn save_master_data(conn: &PgConnection, source: &str, master_data: &MasterData, import_id: Option<uuid::Uuid>) {
+    let known = get_master_data(conn).sources.remove(source).unwrap_or_default();
+
+    let mut changes = diff(&known.employees, &master_data.employees, EMPLOYEE_REGISTERED, EMPLOYEE_RENAMED);
+    changes.extend(diff(&known.actions, &master_data.actions, ACTION_REGISTERED, ACTION_RENAMED));
+
+    insert_changes(conn, source, &changes, import_id);
+}
+
+// Renames by a reverted import are renamed back, unless something renamed
+// them since. Registrations are kept, a name for an unused id does no harm.
+pub fn revert_master_data(conn: &PgConnection, source: &str, import_id: uuid::Uuid) {
+    let history = get_master_data_events(conn);
+    let current = fold_master_data(&history).sources.remove(source).unwrap_or_default();
+
+    let changes: Vec<(&'static str, MasterDataChange)> = history
+        .iter()
+        .filter(|event| event.import_id == Some(import_id))
+        .filter_map(|event| {
+            let change: MasterDataChange = serde_json::from_str(&event.payload).expect("parsing failed");
+            let (renamed, names) = match event.event_type.as_str() {
+                EMPLOYEE_RENAMED => (EMPLOYEE_RENAMED, &current.employees),
+                ACTION_RENAMED => (ACTION_RENAMED, &current.actions),
+                _ => return None,
+            };
+
+            match (names.get(&change.id), change.previous_name) {
+                (Some(name), Some(previous)) if *name == change.name => Some((
+                    renamed,
+                    MasterDataChange { id: change.id, name: previous, previous_name: Some(change.name) },
+                )),
+                _ => None,
+            }
+        })
+        .collect();
+
+    insert_changes(conn, source, &changes, None);
+}
+
+fn insert_changes(conn: &PgConnection, source: &str, changes: &[(&'static str, MasterDataChange)], import_id: Option<uuid::Uuid>) {
+    if changes.is_empty() {
+        return;
+    }
+
+    let new_events: Vec<NewMasterDataEvent> = changes
+        .iter()
+        .map(|(event_type, change)| NewMasterDataEvent {
+            id: uuid::Uuid::new_v4(),
+            event_type,
+            payload: serde_json::to_string(change).expect("stringify failed"),
+            source,
+            import_id,
+        })
+        .collect();
+
+    diesel::insert_into(events::table)
+        .values(&new_events)
+        .execute(conn)
+        .expect("insert failed");
+}
+
+pub fn get_master_data_events(conn: &PgConnection) -> Vec<Event> {
+    use crate::schema::events::dsl::*;
+
+    events
+        .filter(event_type.eq_any(MASTER_DATA_EVENTS.to_vec()))
+        .order((timestamp.asc(), unique_id.asc()))
+        .load::<Event>(conn)
+        .expect("Error loading master data events")
+}
+
+pub fn get_master_data(conn: &PgConnection) -> MasterDataPerSource {
+    fold_master_data(&get_master_data_events(conn))
+}
+
+pub fn get_master_data_as_of(conn: &PgConnection, moment: NaiveDateTime) -> MasterDataPerSource {
+    let history: Vec<Event> = get_master_data_events(conn)
+        .into_iter()
+        .filter(|event| event.timestamp <= moment)
+        .collect();
+
+    fold_master_data(&history)
+}
+
+pub fn fold_master_data(history: &[Event]) -> MasterDataPerSource {
+    history
+        .iter()
+        .fold(MasterDataPerSource::default(), |mut master_data, event| {
+            let change: MasterDataChange = serde_json::from_str(&event.payload).expect("parsing failed");
+            let master_data_of_source = master_data.source_mut(&event.source);
+
+            match event.event_type.as_str() {
+                EMPLOYEE_REGISTERED | EMPLOYEE_RENAMED => {
+                    master_data_of_source.employees.insert(change.id, change.name);
+                }
+                ACTION_REGISTERED | ACTION_RENAMED => {
+                    master_data_of_source.actions.insert(change.id, change.name);
+                }
+                _ => {}
+            }
+
+            master_data
+        })
+}
+
+fn diff(
+    known: &HashMap<u32, String>,
+    imported: &HashMap<u32, String>,
+    registered: &'static str,
+    renamed: &'static str,
+) -> Vec<(&'static str, MasterDataChange)> {
+    let mut ids: Vec<&u32> = imported.keys().collect();
+    ids.sort();
+
+    ids.into_iter()
+        .filter_map(|id| {
+            let name = imported[id].clone();
+
+            match known.get(id) {
+                None => Some((registered, MasterDataChange { id: *id, name, previous_name: None })),
+                Some(previous) if *previous != name => Some((
+                    renamed,
+                    MasterDataChange { id: *id, name, previous_name: Some(previous.clone()) },
+                )),
+                Some(_) => None,
+            }
+        })
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use db_parser::TimeRowEvent;
+    use crate::models::Event;
+    use super::*;
+
+    fn event(event_type: &str, source: &str, payload: &str) -> Event {
+        Event::new(event_type, source, 1, payload.to_string())
+    }
+
+    #[test]
+    fn it_should_only_register_new_and_renamed_master_data() {
+        let mut known = HashMap::new();
+        known.insert(1, "Michel".to_string());
+        known.insert(2, "Jan".to_string());
+        let mut imported = known.clone();
+        imported.insert(2, "Jan de Vries".to_string());
+        imported.insert(3, "Piet".to_string());
+
+        let changes = diff(&known, &imported, EMPLOYEE_REGISTERED, EMPLOYEE_RENAMED);
+
+        assert_eq!(changes, vec![
+            (EMPLOYEE_RENAMED, MasterDataChange { id: 2, name: "Jan de Vries".to_string(), previous_name: Some("Jan".to_string()) }),
+            (EMPLOYEE_REGISTERED, MasterDataChange { id: 3, name: "Piet".to_string(), previous_name: None }),
+        ]);
+    }
+
+    #[test]
+    fn it_should_fold_master_data_history() {
+        let history = vec![
+            event(EMPLOYEE_REGISTERED, "default", "{\"id\":2,\"name\":\"Jan\"}"),
+            event(ACTION_REGISTERED, "default", "{\"id\":1,\"name\":\"Begin/Pauze\"}"),
+            event(EMPLOYEE_RENAMED, "default", "{\"id\":2,\"name\":\"Jan de Vries\",\"previous_name\":\"Jan\"}"),
+        ];
+
+        let master_data = fold_master_data(&history);
+        let master_data = master_data.source("default").unwrap();
+
+        assert_eq!(master_data.employees.get(&2), Some(&"Jan de Vries".to_string()));
+        assert_eq!(master_data.actions.get(&1), Some(&"Begin/Pauze".to_string()));
+    }
+
+    #[test]
+    fn it_should_keep_the_master_data_of_each_terminal_apart() {
+        let history = vec![
+            event(ACTION_REGISTERED, "kas-noord", "{\"id\":2,\"name\":\"Kas\"}"),
+            event(ACTION_REGISTERED, "kas-zuid", "{\"id\":2,\"name\":\"toppen B\"}"),
+        ];
+        let mut time_row: TimeRowEvent = serde_json::from_str(
+            "{\"id\":1,\"employee_id\":7,\"employee\":\"Jan\",\"action_id\":2,\"action\":\"Onbekend\",\"timestamp\":\"2019-01-02T07:00:00\"}"
+        ).unwrap();
+
+        let master_data = fold_master_data(&history);
+        master_data.resolve("kas-zuid", &mut time_row);
+
+        assert_eq!(time_row.action, "toppen B");
+        assert_eq!(master_data.source("kas-noord").unwrap().actions.get(&2), Some(&"Kas".to_string()));
+    }
+}
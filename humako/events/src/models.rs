@@ -6,14 +6,43 @@ extern crate serde_json;
 extern crate uuid;
 
 use super::schema::events;
+use super::schema::rejected_rows;
+use super::schema::import_cursors;
+use super::schema::imports;
+use super::schema::staged_imports;
 
-#[derive(Queryable, Debug, Serialize)]
+#[derive(Queryable, Debug, Clone, Serialize)]
 pub struct Event {
     pub id: uuid::Uuid,
     pub unique_id: i32,
     pub event_type: String,
     pub payload: String,
     pub timestamp: chrono::NaiveDateTime,
+    pub generation: i32,
+    pub source: String,
+    pub import_id: Option<uuid::Uuid>,
+    pub previous_payload: Option<String>,
+    pub previous_import_id: Option<uuid::Uuid>,
+    pub revision: i32,
+}
+
+impl Event {
+    // An event that is not stored (yet), as the first revision of its punch.
+    pub fn new(event_type: &str, source: &str, unique_id: i32, payload: String) -> Event {
+        Event {
+            id: uuid::Uuid::new_v4(),
+            unique_id,
+            event_type: event_type.to_string(),
+            payload,
+            timestamp: chrono::Utc::now().naive_utc(),
+            generation: 0,
+            source: source.to_string(),
+            import_id: None,
+            previous_payload: None,
+            previous_import_id: None,
+            revision: 0,
+        }
+    }
 }
 
 
@@ -24,4 +53,132 @@ pub struct NewEvent<'a> {
     pub unique_id: i32,
     pub event_type: &'a str,
     pub payload: String,
-}
\ No newline at end of file
+    pub generation: i32,
+    pub source: &'a str,
+    pub import_id: Option<uuid::Uuid>,
+    pub revision: i32,
+    pub previous_payload: Option<String>,
+    pub previous_import_id: Option<uuid::Uuid>,
+}
+
+
+#[derive(Queryable, Debug, Serialize)]
+pub struct RejectedRowRecord {
+    pub id: uuid::Uuid,
+    pub unique_id: i32,
+    pub raw: String,
+    pub reason: String,
+    pub timestamp: chrono::NaiveDateTime,
+    pub source: String,
+}
+
+
+#[derive(Insertable, Debug)]
+#[table_name = "rejected_rows"]
+pub struct NewRejectedRow<'a> {
+    pub id: uuid::Uuid,
+    pub unique_id: i32,
+    pub raw: String,
+    pub reason: &'a str,
+    pub source: &'a str,
+}
+
+
+#[derive(Insertable, Debug)]
+#[table_name = "events"]
+pub struct NewMasterDataEvent<'a> {
+    pub id: uuid::Uuid,
+    pub event_type: &'a str,
+    pub payload: String,
+    pub source: &'a str,
+    pub import_id: Option<uuid::Uuid>,
+}
+
+#[derive(Insertable, Debug)]
+#[table_name = "events"]
+pub struct NewCorrectionEvent<'a> {
+    pub id: uuid::Uuid,
+    pub event_type: &'a str,
+    pub payload: String,
+    pub source: &'a str,
+}
+
+#[derive(Queryable, Debug)]
+pub struct ImportCursor {
+    pub source: String,
+    pub last_run_nr: i32,
+    pub timestamp: chrono::NaiveDateTime,
+}
+
+
+#[derive(Insertable, Debug)]
+#[table_name = "import_cursors"]
+pub struct NewImportCursor<'a> {
+    pub source: &'a str,
+    pub last_run_nr: i32,
+}
+
+
+#[derive(Queryable, Debug, Serialize)]
+pub struct ImportRecord {
+    pub id: uuid::Uuid,
+    pub source: String,
+    pub filename: Option<String>,
+    pub sha256: String,
+    pub uploaded_at: chrono::NaiveDateTime,
+    pub new_rows: i32,
+    pub unchanged_rows: i32,
+    pub changed_rows: i32,
+    pub rejected_rows: i32,
+    pub conflicts: i32,
+    pub outcome: String,
+    #[serde(skip_serializing)]
+    pub report: Option<String>,
+    pub error: Option<String>,
+    pub previous_last_run_nr: Option<i32>,
+    pub reverted_at: Option<chrono::NaiveDateTime>,
+}
+
+
+#[derive(Insertable, Debug)]
+#[table_name = "imports"]
+pub struct NewImportRecord<'a> {
+    pub id: uuid::Uuid,
+    pub source: &'a str,
+    pub filename: Option<&'a str>,
+    pub sha256: &'a str,
+    pub new_rows: i32,
+    pub unchanged_rows: i32,
+    pub changed_rows: i32,
+    pub rejected_rows: i32,
+    pub conflicts: i32,
+    pub outcome: &'a str,
+    pub report: Option<String>,
+    pub error: Option<&'a str>,
+    pub previous_last_run_nr: Option<i32>,
+}
+
+
+#[derive(Queryable, Debug, Serialize)]
+pub struct StagedImportRecord {
+    pub id: uuid::Uuid,
+    pub source: String,
+    pub filename: Option<String>,
+    pub sha256: String,
+    pub last_run_nr: Option<i32>,
+    #[serde(skip_serializing)]
+    pub parsed: String,
+    pub staged_at: chrono::NaiveDateTime,
+}
+
+
+#[derive(Insertable, Debug)]
+#[table_name = "staged_imports"]
+pub struct NewStagedImport<'a> {
+    pub id: uuid::Uuid,
+    pub source: &'a str,
+    pub filename: Option<&'a str>,
+    pub sha256: &'a str,
+    pub last_run_nr: Option<i32>,
+    pub parsed: String,
+}
@@ -0,0 +1,255 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt;
+use std::iter;
+
+use db_parser::TimeRowEvent;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+
+use crate::import::{set_last_run_nr, IMPORTED};
+use crate::master_data::revert_master_data;
+use crate::models::{Event, ImportRecord, NewEvent};
+use crate::schema::{events, import_cursors, imports};
+use crate::{get_latest_events, get_revisions, new_revision, VoidedPunch, TIME_ROW_EVENT, TIME_ROW_VOIDED};
+
+pub const REVERTED: &str = "reverted";
+
+#[derive(Debug, Serialize)]
+pub struct RevertReport {
+    pub import_id: uuid::Uuid,
+    pub source: String,
+    pub voided: usize,
+    pub restored: usize,
+    pub last_run_nr: Option<u32>,
+    // Every voided or restored punch, as it was and as it is restored, to
+    // find the work days to derive again
+    #[serde(skip_serializing)]
+    pub punches: Vec<(String, TimeRowEvent)>,
+}
+
+#[derive(Debug)]
+pub enum RevertError {
+    UnknownImport(uuid::Uuid),
+    NotImported { import_id: uuid::Uuid, outcome: String },
+    NotLatest { import_id: uuid::Uuid, later: uuid::Uuid },
+    Database(diesel::result::Error),
+}
+
+impl fmt::Display for RevertError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RevertError::UnknownImport(import_id) => write!(f, "no import {}", import_id),
+            RevertError::NotImported { import_id, outcome } => {
+                write!(f, "import {} can't be reverted, it is {}", import_id, outcome)
+            }
+            RevertError::NotLatest { import_id, later } => {
+                write!(f, "import {} can't be reverted before import {} of the same terminal", import_id, later)
+            }
+            RevertError::Database(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl Error for RevertError {}
+
+impl From<diesel::result::Error> for RevertError {
+    fn from(error: diesel::result::Error) -> Self {
+        RevertError::Database(error)
+    }
+}
+
+// Punches the import inserted are voided, punches it changed get the payload
+// they had before any reverted import back as a new revision. Imports of a terminal are
+// reverted latest first, so the cursor can go back to where it was before
+// the import.
+pub fn revert_import(conn: &PgConnection, import_id: uuid::Uuid) -> Result<RevertReport, RevertError> {
+    conn.transaction(|| {
+        let record = imports::table
+            .find(import_id)
+            .first::<ImportRecord>(conn)
+            .optional()?
+            .ok_or(RevertError::UnknownImport(import_id))?;
+
+        if record.outcome != IMPORTED {
+            return Err(RevertError::NotImported { import_id, outcome: record.outcome });
+        }
+
+        let later = imports::table
+            .filter(imports::source.eq(&record.source))
+            .filter(imports::outcome.eq(IMPORTED))
+            .filter(imports::uploaded_at.gt(record.uploaded_at))
+            .select(imports::id)
+            .first::<uuid::Uuid>(conn)
+            .optional()?;
+        if let Some(later) = later {
+            return Err(RevertError::NotLatest { import_id, later });
+        }
+
+        let reverted: HashSet<uuid::Uuid> = imports::table
+            .filter(imports::source.eq(&record.source))
+            .filter(imports::outcome.eq(REVERTED))
+            .select(imports::id)
+            .load::<uuid::Uuid>(conn)?
+            .into_iter()
+            .chain(iter::once(import_id))
+            .collect();
+
+        let ids = events::table
+            .filter(events::import_id.eq(import_id))
+            .filter(events::event_type.eq(TIME_ROW_EVENT))
+            .select(events::unique_id)
+            .distinct()
+            .load::<i32>(conn)?;
+        let mut histories = get_revisions(conn, &record.source, TIME_ROW_EVENT, ids);
+        histories.retain(|_, history| history.last().and_then(|event| event.import_id) == Some(import_id));
+
+        let mut punches = vec![];
+        let mut revisions: Vec<NewEvent> = vec![];
+        let mut to_void: Vec<&Event> = vec![];
+        for history in histories.values() {
+            let stored = &history[history.len() - 1];
+            punches.push(punch_of(&record.source, &stored.payload));
+
+            match restore_point(history, &reverted) {
+                Some(restored) => {
+                    punches.push(punch_of(&record.source, &restored.payload));
+                    revisions.push(new_revision(
+                        TIME_ROW_EVENT,
+                        &record.source,
+                        stored.unique_id,
+                        stored.generation,
+                        restored.payload.clone(),
+                        restored.import_id,
+                        Some(stored),
+                    ));
+                }
+                None => to_void.push(stored),
+            }
+        }
+        let restored = revisions.len();
+
+        let void_ids = to_void.iter().map(|event| event.unique_id).collect();
+        let voids = get_latest_events(conn, &record.source, TIME_ROW_VOIDED, void_ids);
+        revisions.extend(to_void.iter().map(|event| {
+            let voided = VoidedPunch { id: event.unique_id as u32, generation: event.generation, import_id };
+            let payload = serde_json::to_string(&voided).expect("stringify failed");
+
+            new_revision(TIME_ROW_VOIDED, &record.source, event.unique_id, event.generation, payload, Some(import_id), voids.get(&(event.unique_id, event.generation)))
+        }));
+
+        if !revisions.is_empty() {
+            diesel::insert_into(events::table).values(&revisions).execute(conn)?;
+        }
+
+        revert_master_data(conn, &record.source, import_id);
+
+        let last_run_nr = record.previous_last_run_nr.map(|run_nr| run_nr as u32);
+        match last_run_nr {
+            Some(last_run_nr) => set_last_run_nr(conn, &record.source, last_run_nr),
+            None => {
+                diesel::delete(import_cursors::table.find(&record.source)).execute(conn)?;
+            }
+        }
+
+        diesel::update(imports::table.find(import_id))
+            .set((
+                imports::outcome.eq(REVERTED),
+                imports::reverted_at.eq(diesel::dsl::now.nullable()),
+            ))
+            .execute(conn)?;
+
+        Ok(RevertReport {
+            import_id,
+            source: record.source,
+            voided: to_void.len(),
+            restored,
+            last_run_nr,
+            punches,
+        })
+    })
+}
+
+// The revision a punch goes back to, the latest one no reverted import
+// wrote. A restored revision is stored again under the import that wrote it,
+// so reverting that import later on goes back further.
+fn restore_point<'a>(history: &'a [Event], reverted: &HashSet<uuid::Uuid>) -> Option<&'a Event> {
+    history
+        .iter()
+        .rev()
+        .find(|event| event.import_id.filter(|import_id| reverted.contains(import_id)).is_none())
+}
+
+fn punch_of(source: &str, payload: &str) -> (String, TimeRowEvent) {
+    let time_row: TimeRowEvent = serde_json::from_str(payload).expect("parsing failed");
+
+    (source.to_string(), time_row)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use crate::models::Event;
+    use crate::{drop_voided, TIME_ROW_EVENT, TIME_ROW_VOIDED};
+    use super::restore_point;
+
+    fn event(event_type: &str, import_id: uuid::Uuid) -> Event {
+        Event { import_id: Some(import_id), ..Event::new(event_type, "default", 1, "{}".to_string()) }
+    }
+
+    #[test]
+    fn it_should_only_void_what_the_reverted_import_stored() {
+        let reverted = uuid::Uuid::new_v4();
+        let reimported = uuid::Uuid::new_v4();
+
+        assert!(drop_voided(vec![event(TIME_ROW_EVENT, reverted), event(TIME_ROW_VOIDED, reverted)]).is_empty());
+
+        let events = drop_voided(vec![event(TIME_ROW_EVENT, reimported), event(TIME_ROW_VOIDED, reverted)]);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].import_id, Some(reimported));
+    }
+
+    fn revision(revision: i32, payload: &str, import_id: Option<uuid::Uuid>) -> Event {
+        Event { revision, import_id, ..Event::new(TIME_ROW_EVENT, "default", 1, payload.to_string()) }
+    }
+
+    // What revert_import stores for a punch it restores
+    fn restore(history: &mut Vec<Event>, reverted: &HashSet<uuid::Uuid>) {
+        let restored = restore_point(history, reverted).cloned().unwrap();
+        let next = history.len() as i32;
+        history.push(revision(next, &restored.payload, restored.import_id));
+    }
+
+    #[test]
+    fn it_should_not_bring_back_a_reverted_import_when_reverting_the_one_before() {
+        let (a, b) = (uuid::Uuid::new_v4(), uuid::Uuid::new_v4());
+        let mut history = vec![revision(0, "first", None), revision(1, "by A", Some(a)), revision(2, "by B", Some(b))];
+        let mut reverted = HashSet::new();
+
+        reverted.insert(b);
+        restore(&mut history, &reverted);
+        assert_eq!(history[3].payload, "by A");
+        assert_eq!(history[3].import_id, Some(a));
+
+        reverted.insert(a);
+        restore(&mut history, &reverted);
+        assert_eq!(history[4].payload, "first");
+        assert_eq!(history[4].import_id, None);
+    }
+
+    #[test]
+    fn it_should_void_a_punch_the_import_before_inserted_once_both_are_reverted() {
+        let (a, b) = (uuid::Uuid::new_v4(), uuid::Uuid::new_v4());
+        let mut history = vec![revision(0, "by A", Some(a)), revision(1, "by B", Some(b))];
+        let mut reverted = HashSet::new();
+
+        reverted.insert(b);
+        restore(&mut history, &reverted);
+        reverted.insert(a);
+        assert!(restore_point(&history, &reverted).is_none());
+
+        let latest = history.pop().unwrap();
+        assert!(drop_voided(vec![latest, event(TIME_ROW_VOIDED, a)]).is_empty());
+    }
+}
@@ -5,5 +5,65 @@ table! {
         event_type -> Varchar,
         payload -> Text,
         timestamp -> Timestamp,
+        generation -> Int4,
+        source -> Varchar,
+        import_id -> Nullable<Uuid>,
+        previous_payload -> Nullable<Text>,
+        previous_import_id -> Nullable<Uuid>,
+        revision -> Int4,
     }
 }
+
+table! {
+    rejected_rows (id) {
+        id -> Uuid,
+        unique_id -> Int4,
+        raw -> Text,
+        reason -> Varchar,
+        timestamp -> Timestamp,
+        source -> Varchar,
+    }
+}
+
+table! {
+    import_cursors (source) {
+        source -> Varchar,
+        last_run_nr -> Int4,
+        timestamp -> Timestamp,
+    }
+}
+
+table! {
+    imports (id) {
+        id -> Uuid,
+        source -> Varchar,
+        filename -> Nullable<Varchar>,
+        sha256 -> Varchar,
+        uploaded_at -> Timestamp,
+        new_rows -> Int4,
+        unchanged_rows -> Int4,
+        changed_rows -> Int4,
+        rejected_rows -> Int4,
+        conflicts -> Int4,
+        outcome -> Varchar,
+        report -> Nullable<Text>,
+        error -> Nullable<Text>,
+        previous_last_run_nr -> Nullable<Int4>,
+        reverted_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    staged_imports (id) {
+        id -> Uuid,
+        source -> Varchar,
+        filename -> Nullable<Varchar>,
+        sha256 -> Varchar,
+        last_run_nr -> Nullable<Int4>,
+        parsed -> Text,
+        staged_at -> Timestamp,
+    }
+}
+
+joinable!(events -> imports (import_id));
+allow_tables_to_appear_in_same_query!(events, imports);
@@ -0,0 +1,237 @@
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fmt;
+
+use db_parser::{MasterDataPerSource, ParsedDb, RejectedRow, TimeRowEvent};
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+
+use crate::import::{get_last_run_nr, import, plan_stored_import, Change, Conflict, ImportReport, Upload};
+use crate::models::{Event, NewStagedImport, StagedImportRecord};
+use crate::schema::staged_imports;
+use crate::{get_events, get_master_data, Punch, TIME_ROW_EVENT};
+
+// What committing a staged import would do to the stored events.
+#[derive(Debug, Serialize)]
+pub struct ImportDiff {
+    pub staged_id: uuid::Uuid,
+    pub source: String,
+    pub filename: Option<String>,
+    pub new: Vec<Punch>,
+    pub changed: Vec<Change>,
+    pub unchanged: usize,
+    pub conflicts: Vec<Conflict>,
+    pub rejected_rows: Vec<RejectedRow>,
+    // Every new punch and every changed punch before and after, to find the
+    // work days it affects
+    #[serde(skip_serializing)]
+    pub punches: Vec<(String, TimeRowEvent)>,
+    pub employees: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct Preview {
+    pub diff: ImportDiff,
+    // All events as they would be stored after committing
+    pub events: Vec<Event>,
+    pub master_data: MasterDataPerSource,
+}
+
+#[derive(Debug)]
+pub enum StagingError {
+    UnknownStagedImport(uuid::Uuid),
+    Stale { staged_id: uuid::Uuid, staged_last_run_nr: Option<u32>, last_run_nr: Option<u32> },
+    Database(diesel::result::Error),
+}
+
+impl fmt::Display for StagingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StagingError::UnknownStagedImport(staged_id) => write!(f, "no staged import {}", staged_id),
+            StagingError::Stale { staged_id, staged_last_run_nr, last_run_nr } => write!(
+                f,
+                "staged import {} was read up to TRD_RunNr {:?} but the terminal has been imported up to {:?} since, upload it again",
+                staged_id, staged_last_run_nr, last_run_nr
+            ),
+            StagingError::Database(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl Error for StagingError {}
+
+impl From<diesel::result::Error> for StagingError {
+    fn from(error: diesel::result::Error) -> Self {
+        StagingError::Database(error)
+    }
+}
+
+// The file has to be parsed with the cursor it is staged with, the staged
+// import goes stale as soon as another import moves that cursor.
+pub fn stage_import(conn: &PgConnection, upload: &Upload, last_run_nr: Option<u32>, parsed: &ParsedDb) -> uuid::Uuid {
+    let id = uuid::Uuid::new_v4();
+
+    diesel::insert_into(staged_imports::table)
+        .values(&NewStagedImport {
+            id,
+            source: upload.source,
+            filename: upload.filename,
+            sha256: &upload.sha256,
+            last_run_nr: last_run_nr.map(|run_nr| run_nr as i32),
+            parsed: serde_json::to_string(parsed).expect("stringify failed"),
+        })
+        .execute(conn)
+        .expect("insert failed");
+
+    id
+}
+
+pub fn get_staged_imports(conn: &PgConnection) -> Vec<StagedImportRecord> {
+    staged_imports::table
+        .order(staged_imports::staged_at.desc())
+        .load::<StagedImportRecord>(conn)
+        .expect("Error loading staged imports")
+}
+
+pub fn preview_staged(conn: &PgConnection, staged_id: uuid::Uuid) -> Result<Preview, StagingError> {
+    let (record, parsed) = load_staged(conn, staged_id)?;
+    check_fresh(conn, &record)?;
+
+    let plan = plan_stored_import(conn, &record.source, parsed.time_rows, parsed.imported_rows);
+
+    let mut master_data = get_master_data(conn);
+    let master_data_of_source = master_data.source_mut(&record.source);
+    master_data_of_source.employees.extend(parsed.master_data.employees);
+    master_data_of_source.actions.extend(parsed.master_data.actions);
+
+    let touched: Vec<_> = plan.new
+        .iter()
+        .map(|punch| &punch.time_row)
+        .chain(plan.changed.iter().flat_map(|change| vec![&change.previous, &change.punch.time_row]))
+        .collect();
+    let punches: Vec<(String, TimeRowEvent)> = touched
+        .iter()
+        .map(|time_row| (record.source.clone(), (*time_row).clone()))
+        .collect();
+    let employees: BTreeSet<String> = touched
+        .iter()
+        .map(|time_row| {
+            let mut time_row = (*time_row).clone();
+            master_data.resolve(&record.source, &mut time_row);
+
+            time_row.employee
+        })
+        .collect();
+
+    let mut events = get_events(conn);
+    for change in &plan.changed {
+        let punch = &change.punch;
+        events
+            .iter_mut()
+            .filter(|event| {
+                event.source == record.source
+                    && event.event_type == TIME_ROW_EVENT
+                    && event.unique_id == punch.time_row.id as i32
+                    && event.generation == punch.generation
+            })
+            .for_each(|event| {
+                event.payload = punch.time_row.stringify();
+                event.import_id = Some(staged_id);
+            });
+    }
+    events.extend(plan.new.iter().map(|punch| staged_event(&record, punch)));
+
+    Ok(Preview {
+        diff: ImportDiff {
+            staged_id,
+            source: record.source,
+            filename: record.filename,
+            new: plan.new,
+            changed: plan.changed,
+            unchanged: plan.unchanged,
+            conflicts: plan.conflicts,
+            rejected_rows: parsed.rejected_rows,
+            punches,
+            employees: employees.into_iter().collect(),
+        },
+        events,
+        master_data,
+    })
+}
+
+pub fn commit_staged(conn: &PgConnection, staged_id: uuid::Uuid) -> Result<ImportReport, StagingError> {
+    conn.transaction(|| {
+        let (record, parsed) = load_staged(conn, staged_id)?;
+        check_fresh(conn, &record)?;
+
+        let upload = Upload {
+            source: &record.source,
+            filename: record.filename.as_deref(),
+            sha256: record.sha256.clone(),
+        };
+        let report = import(conn, &upload, parsed);
+
+        diesel::delete(staged_imports::table.find(staged_id)).execute(conn)?;
+
+        Ok(report)
+    })
+}
+
+pub fn discard_staged(conn: &PgConnection, staged_id: uuid::Uuid) -> Result<(), StagingError> {
+    match diesel::delete(staged_imports::table.find(staged_id)).execute(conn)? {
+        0 => Err(StagingError::UnknownStagedImport(staged_id)),
+        _ => Ok(()),
+    }
+}
+
+fn load_staged(conn: &PgConnection, staged_id: uuid::Uuid) -> Result<(StagedImportRecord, ParsedDb), StagingError> {
+    let record = staged_imports::table
+        .find(staged_id)
+        .first::<StagedImportRecord>(conn)
+        .optional()?
+        .ok_or(StagingError::UnknownStagedImport(staged_id))?;
+    let parsed: ParsedDb = serde_json::from_str(&record.parsed).expect("parsing failed");
+
+    Ok((record, parsed))
+}
+
+fn check_fresh(conn: &PgConnection, record: &StagedImportRecord) -> Result<(), StagingError> {
+    let staged_last_run_nr = record.last_run_nr.map(|run_nr| run_nr as u32);
+    let last_run_nr = get_last_run_nr(conn, &record.source);
+
+    if staged_last_run_nr == last_run_nr {
+        Ok(())
+    } else {
+        Err(StagingError::Stale { staged_id: record.id, staged_last_run_nr, last_run_nr })
+    }
+}
+
+fn staged_event(record: &StagedImportRecord, punch: &Punch) -> Event {
+    Event {
+        timestamp: record.staged_at,
+        generation: punch.generation,
+        import_id: Some(record.id),
+        ..Event::new(TIME_ROW_EVENT, &record.source, punch.time_row.id as i32, punch.time_row.stringify())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use db_parser::{MasterData, ParsedDb, TimeRowEvent};
+
+    #[test]
+    fn it_should_keep_a_staged_file_intact() {
+        let time_row: TimeRowEvent = serde_json::from_str(
+            "{\"id\":3,\"employee_id\":7,\"employee\":\"Michel\",\"action_id\":2,\"action\":\"Kas\",\"timestamp\":\"2019-01-02T07:00:00\"}"
+        ).unwrap();
+        let mut master_data = MasterData::default();
+        master_data.employees.insert(7, "Michel".to_string());
+        let parsed = ParsedDb { time_rows: vec![time_row], imported_rows: vec![], rejected_rows: vec![], master_data };
+
+        let staged: ParsedDb = serde_json::from_str(&serde_json::to_string(&parsed).unwrap()).unwrap();
+
+        assert!(staged.time_rows[0].same_punch(&parsed.time_rows[0]));
+        assert_eq!(staged.master_data.employees.get(&7), Some(&"Michel".to_string()));
+    }
+}
@@ -4,10 +4,56 @@ use events::establish_connection;
 use events::print_events;
 use events::save_events;
 use web::serve;
+use std::env;
+use std::process;
 
 fn main() {
-    println!("serving...");
+    let args: Vec<String> = env::args().collect();
+    let settings = match worksheets::Settings::load() {
+        Ok(settings) => settings,
+        Err(error) => {
+            eprintln!("{}", error);
+            process::exit(1);
+        }
+    };
 
+    match args.get(1).map(|command| command.as_str()) {
+        Some("revert") => revert(args.get(2), &settings),
+        _ => {
+            println!("serving...");
 
-    serve();
+
+            serve(settings);
+        }
+    }
+}
+
+fn revert(import_id: Option<&String>, settings: &worksheets::Settings) {
+    let import_id = match import_id.and_then(|id| uuid::Uuid::parse_str(id).ok()) {
+        Some(import_id) => import_id,
+        None => {
+            eprintln!("usage: humako revert <import id>");
+            process::exit(1);
+        }
+    };
+
+    let conn = establish_connection();
+    match events::revert_import(&conn, import_id) {
+        Ok(report) => {
+            println!(
+                "Reverted import {} of {}: {} punches voided, {} restored",
+                report.import_id, report.source, report.voided, report.restored
+            );
+
+            let master_data = events::get_master_data(&conn);
+            let days = worksheets::derive_days(events::get_events(&conn), &master_data, &report.punches, settings);
+            for (day, work_day) in days {
+                println!("{}: {:?}", day, work_day);
+            }
+        }
+        Err(error) => {
+            eprintln!("Could not revert import: {}", error);
+            process::exit(1);
+        }
+    }
 }
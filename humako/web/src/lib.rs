@@ -7,19 +7,40 @@ extern crate router;
 extern crate iron;
 extern crate multipart;
 
-use std::io::{self, Write};
+use std::io::{self, Read, Write};
 use multipart::mock::StdoutTee;
 use multipart::server::{Multipart, Entries, SaveResult};
 use multipart::server::save::SavedData;
 use iron::prelude::*;
 use iron::status;
-use std::collections::HashMap;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
 use iron_cors::CorsMiddleware;
 use std::env;
+use std::sync::Arc;
 use iron::prelude::*;
-use iron::Handler;
+use iron::{BeforeMiddleware, Handler};
 
-pub fn serve() {
+// Hands the settings `serve` was started with to every request.
+struct SharedSettings(Arc<worksheets::Settings>);
+
+impl iron::typemap::Key for SharedSettings {
+    type Value = Arc<worksheets::Settings>;
+}
+
+impl BeforeMiddleware for SharedSettings {
+    fn before(&self, request: &mut Request) -> IronResult<()> {
+        request.extensions.insert::<SharedSettings>(self.0.clone());
+
+        Ok(())
+    }
+}
+
+fn shared_settings(request: &Request) -> Arc<worksheets::Settings> {
+    request.extensions.get::<SharedSettings>().expect("settings are shared with every request").clone()
+}
+
+pub fn serve(settings: worksheets::Settings) {
     let mut router = router::Router::new();
 
     router.route(iron::method::Get, "/hello", |_: &mut Request| {
@@ -27,55 +48,262 @@ pub fn serve() {
     }, "hello");
 
     router.route(iron::method::Get, "/day/:date", get_work_sheet, "get_events");
+    router.route(iron::method::Get, "/v2/day/:date", get_work_day, "get_work_day");
+    router.route(iron::method::Get, "/day/:date/employee/:id/timeline", get_timeline, "get_timeline");
+    router.route(iron::method::Get, "/period/:kind/:id", get_period, "get_period");
+    router.route(iron::method::Get, "/anomalies", get_anomalies, "get_anomalies");
     router.route(iron::method::Get, "/available-days", get_available_days, "get_available_days");
+    router.route(iron::method::Get, "/terminals", get_terminals, "get_terminals");
+    router.route(iron::method::Get, "/imports", get_imports, "get_imports");
+    router.route(iron::method::Post, "/imports/:id/revert", revert_import, "revert_import");
+
+    router.route(iron::method::Get, "/corrections", get_corrections, "get_corrections");
+    router.route(iron::method::Post, "/corrections", save_correction, "save_correction");
 
-    router.route(iron::method::Post, "/upload", process_request, "hello2");
+    router.route(iron::method::Post, "/upload", |request: &mut Request| process_request(request, process_entries), "hello2");
+    router.route(iron::method::Post, "/staged", |request: &mut Request| {
+        let settings = shared_settings(request);
+
+        process_request(request, |entries| stage_entries(entries, &settings))
+    }, "stage_import");
+    router.route(iron::method::Get, "/staged", get_staged_imports, "get_staged_imports");
+    router.route(iron::method::Get, "/staged/:id", get_staged_import, "get_staged_import");
+    router.route(iron::method::Post, "/staged/:id/commit", commit_staged_import, "commit_staged_import");
+    router.route(iron::method::Post, "/staged/:id/discard", discard_staged_import, "discard_staged_import");
     let cors_middleware = CorsMiddleware::with_allow_any();
     let mut chain = Chain::new(router);
+    chain.link_before(SharedSettings(Arc::new(settings)));
     chain.link_around(cors_middleware);
 
     Iron::new(chain).http("0.0.0.0:3010");
 }
 
+fn query_param(request: &Request, name: &str) -> Option<String> {
+    let url: &iron::url::Url = request.url.as_ref();
+
+    url.query_pairs()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.into_owned())
+}
+
+// Worksheets merge the punches of all terminals unless one is asked for with
+// `?terminal=`. With `?as_of=` they are derived from what was imported up to
+// that moment, the events then hold every revision.
+fn load_events(conn: &diesel::pg::PgConnection, request: &Request, as_of: Option<chrono::NaiveDateTime>) -> (Vec<events::models::Event>, db_parser::MasterDataPerSource) {
+    let terminal = query_param(request, "terminal");
+
+    match as_of {
+        Some(as_of) => (
+            events::get_event_history(conn, terminal.as_ref().map(|terminal| terminal.as_str())),
+            events::get_master_data_as_of(conn, as_of),
+        ),
+        None => (
+            match terminal {
+                Some(terminal) => events::get_source_events(conn, &terminal),
+                None => events::get_events(conn),
+            },
+            events::get_master_data(conn),
+        ),
+    }
+}
+
+// Accepts a moment or a day, a day includes everything imported on it.
+fn as_of(request: &Request) -> Result<Option<chrono::NaiveDateTime>, Response> {
+    match query_param(request, "as_of") {
+        Some(as_of) => chrono::NaiveDateTime::parse_from_str(&as_of, "%Y-%m-%dT%H:%M:%S")
+            .or_else(|_| chrono::NaiveDate::parse_from_str(&as_of, "%Y-%m-%d").map(|date| date.and_hms(23, 59, 59)))
+            .map(Some)
+            .map_err(|_| Response::with((status::BadRequest, "Incorrect as_of submitted"))),
+        None => Ok(None),
+    }
+}
+
+fn get_terminals(_request: &mut Request) -> IronResult<Response> {
+    let conn = events::establish_connection();
+    let terminals = events::get_sources(&conn);
+
+    Ok(Response::with((status::Ok, serde_json::to_string(&terminals).unwrap())))
+}
+
+fn get_imports(_request: &mut Request) -> IronResult<Response> {
+    let conn = events::establish_connection();
+    let imports = events::get_imports(&conn);
+
+    Ok(Response::with((status::Ok, serde_json::to_string(&imports).unwrap())))
+}
+
+#[derive(Serialize)]
+struct Reverted {
+    report: events::RevertReport,
+    days: BTreeMap<chrono::NaiveDate, worksheets::WorkDay>,
+}
+
+fn revert_import(request: &mut Request) -> IronResult<Response> {
+    let params = request.extensions.get::<router::Router>().unwrap();
+    let import_id = match params.find("id").and_then(|id| uuid::Uuid::parse_str(id).ok()) {
+        Some(import_id) => import_id,
+        None => return Ok(Response::with((status::BadRequest, "Incorrect import id submitted"))),
+    };
+
+    let conn = events::establish_connection();
+    match events::revert_import(&conn, import_id) {
+        Ok(report) => {
+            let master_data = events::get_master_data(&conn);
+            let days = worksheets::derive_days(events::get_events(&conn), &master_data, &report.punches, &shared_settings(request));
+
+            Ok(Response::with((status::Ok, serde_json::to_string(&Reverted { report, days }).unwrap())))
+        }
+        Err(error @ events::RevertError::UnknownImport(_)) => Ok(Response::with((status::NotFound, error.to_string()))),
+        Err(error @ events::RevertError::Database(_)) => Ok(Response::with((status::InternalServerError, error.to_string()))),
+        Err(error) => Ok(Response::with((status::Conflict, error.to_string()))),
+    }
+}
+
+fn get_corrections(_request: &mut Request) -> IronResult<Response> {
+    let conn = events::establish_connection();
+    let corrections = events::get_corrections(&conn);
+
+    Ok(Response::with((status::Ok, serde_json::to_string(&corrections).unwrap())))
+}
+
+fn save_correction(request: &mut Request) -> IronResult<Response> {
+    let mut body = String::new();
+    if let Err(error) = request.body.read_to_string(&mut body) {
+        return Ok(Response::with((status::BadRequest, format!("error reading request: {}", error))));
+    }
+
+    let correction: events::PunchCorrection = match serde_json::from_str(&body) {
+        Ok(correction) => correction,
+        Err(error) => return Ok(Response::with((status::BadRequest, format!("Incorrect correction submitted: {}", error)))),
+    };
+
+    let conn = events::establish_connection();
+    match events::save_correction(&conn, &correction) {
+        Ok(record) => Ok(Response::with((status::Created, serde_json::to_string(&record).unwrap()))),
+        Err(error) => Ok(Response::with((status::UnprocessableEntity, error.to_string()))),
+    }
+}
+
+// Only the days that have anomalies, oldest first.
+fn get_anomalies(request: &mut Request) -> IronResult<Response> {
+    let as_of = match as_of(request) {
+        Ok(as_of) => as_of,
+        Err(response) => return Ok(response),
+    };
+    let conn = events::establish_connection();
+    let (events, master_data) = load_events(&conn, request, as_of);
+
+    let anomalies = worksheets::derive_work_sheet(events, &master_data, as_of, &shared_settings(request)).anomalies();
+
+    Ok(Response::with((status::Ok, serde_json::to_string(&anomalies).unwrap())))
+}
+
 fn get_available_days(request: &mut Request) -> IronResult<Response> {
+    let as_of = match as_of(request) {
+        Ok(as_of) => as_of,
+        Err(response) => return Ok(response),
+    };
     let conn = events::establish_connection();
-    let events = events::get_events(&conn);
+    let (events, master_data) = load_events(&conn, request, as_of);
 
-    let worksheet = worksheets::derive_work_sheet(events);
+    let worksheet = worksheets::derive_work_sheet(events, &master_data, as_of, &shared_settings(request));
 
-    let available_days: Vec<&chrono::NaiveDate> = worksheet.keys().collect();
-    let json = serde_json::to_string(&available_days).unwrap();
+    let json = serde_json::to_string(&worksheet.dates()).unwrap();
 
     Ok(Response::with((status::Ok, json)))
 }
 
+// The first version of the API has the minutes per action per employee.
 fn get_work_sheet(request: &mut Request) -> IronResult<Response> {
-    let params = request.extensions.get::<router::Router>().unwrap();
-    match &params.find("date")
-        .and_then(|d: &str| {
-            chrono::NaiveDate::parse_from_str(d, "%Y-%m-%d").ok()
-        }) {
-        Some(date) => {
-            let conn = events::establish_connection();
-            let events = events::get_events(&conn);
+    match derive_day(request) {
+        Ok(day) => Ok(Response::with((status::Ok, serde_json::to_string(&day.minutes_per_employee()).unwrap()))),
+        Err(response) => Ok(response),
+    }
+}
 
-            let worksheet = worksheets::derive_work_sheet(events);
+fn get_work_day(request: &mut Request) -> IronResult<Response> {
+    match derive_day(request) {
+        Ok(day) => Ok(Response::with((status::Ok, serde_json::to_string(&day).unwrap()))),
+        Err(response) => Ok(response),
+    }
+}
 
+// `:id` is the employee id, or the key `/day` lists the employee under, for
+// punches imported before ids were kept. Router leaves that key escaped.
+fn get_timeline(request: &mut Request) -> IronResult<Response> {
+    let key = match request.extensions
+        .get::<router::Router>()
+        .and_then(|params| params.find("id")) {
+        Some(key) => iron::url::percent_encoding::percent_decode(key.as_bytes()).decode_utf8_lossy().into_owned(),
+        None => return Ok(Response::with((status::BadRequest, "Incorrect employee id submitted"))),
+    };
+    let day = match derive_day(request) {
+        Ok(day) => day,
+        Err(response) => return Ok(response),
+    };
 
-            let day = worksheet.get(&date);
+    let employee = key.parse::<u32>()
+        .ok()
+        .and_then(|employee_id| day.employees.values().find(|employee| employee.employee_id == Some(employee_id)))
+        .or_else(|| day.employees.get(&key));
 
-            match day {
-                Some(data) => {
-                    Ok(Response::with((status::Ok, serde_json::to_string(&data).unwrap())))
-                }
-                None => Ok(Response::with((status::BadRequest, "Incorrect date submitted")))
-            }
-        }
-        None => Ok(Response::with((status::BadRequest, "Incorrect date submitted")))
+    match employee {
+        Some(employee) => Ok(Response::with((status::Ok, serde_json::to_string(&employee.segments).unwrap()))),
+        None => Ok(Response::with((status::NotFound, format!("No punches of employee {} on {}", key, day.date)))),
     }
 }
 
-fn process_request(request: &mut Request) -> IronResult<Response> {
+// `/period/week/2019-W02`, `/period/month/2019-01` or `/period/four-weeks/2019-P03`
+fn get_period(request: &mut Request) -> IronResult<Response> {
+    let as_of = match as_of(request) {
+        Ok(as_of) => as_of,
+        Err(response) => return Ok(response),
+    };
+    let settings = shared_settings(request);
+    let period = request.extensions
+        .get::<router::Router>()
+        .and_then(|params| {
+            let kind = match params.find("kind")? {
+                "week" => worksheets::PeriodKind::IsoWeek,
+                "month" => worksheets::PeriodKind::Month,
+                "four-weeks" => worksheets::PeriodKind::FourWeekly,
+                _ => return None,
+            };
+
+            worksheets::Period::parse(kind, params.find("id")?, &settings.periods)
+        });
+    let period = match period {
+        Some(period) => period,
+        None => return Ok(Response::with((status::BadRequest, "Incorrect period submitted"))),
+    };
+
+    let conn = events::establish_connection();
+    let (events, master_data) = load_events(&conn, request, as_of);
+
+    let worksheet = worksheets::derive_work_sheet(events, &master_data, as_of, &settings);
+
+    Ok(Response::with((status::Ok, serde_json::to_string(&worksheet.period(&period)).unwrap())))
+}
+
+fn derive_day(request: &Request) -> Result<worksheets::WorkDay, Response> {
+    let as_of = as_of(request)?;
+    let date = request.extensions
+        .get::<router::Router>()
+        .and_then(|params| params.find("date"))
+        .and_then(|d: &str| chrono::NaiveDate::parse_from_str(d, "%Y-%m-%d").ok())
+        .ok_or_else(|| Response::with((status::BadRequest, "Incorrect date submitted")))?;
+
+    let conn = events::establish_connection();
+    let (events, master_data) = load_events(&conn, request, as_of);
+
+    let mut worksheet = worksheets::derive_work_sheet(events, &master_data, as_of, &shared_settings(request));
+
+    worksheet.days
+        .remove(&date)
+        .ok_or_else(|| Response::with((status::BadRequest, "Incorrect date submitted")))
+}
+
+fn process_request(request: &mut Request, process_entries: impl Fn(Entries) -> IronResult<Response>) -> IronResult<Response> {
     // Getting a multipart reader wrapper
     match Multipart::from_request(request) {
         Ok(mut multipart) => {
@@ -103,27 +331,169 @@ fn process_request(request: &mut Request) -> IronResult<Response> {
     }
 }
 
-fn process_entries(entries: Entries) -> IronResult<Response> {
-    let field = entries.fields
+struct UploadedFile<'a> {
+    terminal: String,
+    path: &'a PathBuf,
+    filename: Option<&'a str>,
+    sha256: String,
+}
+
+impl<'a> UploadedFile<'a> {
+    fn upload(&self) -> events::Upload {
+        events::Upload {
+            source: &self.terminal,
+            filename: self.filename,
+            sha256: self.sha256.clone(),
+        }
+    }
+}
+
+fn read_upload(entries: &Entries) -> Result<UploadedFile, Response> {
+    let field = match entries.fields
         .get(&"file".to_string())
-        .expect("Please upload file under key \"file\"")
-        .first()
-        .expect("Please upload file under key \"file\"");
+        .and_then(|fields| fields.first()) {
+        Some(field) => field,
+        None => return Err(Response::with((status::BadRequest, "Please upload file under key \"file\""))),
+    };
+
+    // Installations with a single terminal don't have to name it
+    let terminal = match entries.fields
+        .get(&"terminal".to_string())
+        .and_then(|fields| fields.first())
+        .map(|field| &field.data) {
+        Some(SavedData::Text(terminal)) if !terminal.trim().is_empty() => terminal.trim().to_string(),
+        Some(_) => return Err(Response::with((status::BadRequest, "Terminal should be a non-empty text field"))),
+        None => events::DEFAULT_SOURCE.to_string(),
+    };
 
     match &field.data {
-        SavedData::File(path, _) => {
-            let time_events = db_parser::parse_db(path);
+        SavedData::File(path, _) => match events::file_sha256(path) {
+            Ok(sha256) => Ok(UploadedFile {
+                terminal,
+                path,
+                filename: field.headers.filename.as_ref().map(|filename| filename.as_str()),
+                sha256,
+            }),
+            Err(error) => Err(Response::with((status::InternalServerError, format!("Could not read uploaded file: {}", error)))),
+        },
+        _ => Err(Response::with((status::BadRequest, "Nope"))),
+    }
+}
+
+fn process_entries(entries: Entries) -> IronResult<Response> {
+    let file = match read_upload(&entries) {
+        Ok(file) => file,
+        Err(response) => return Ok(response),
+    };
+    let upload = file.upload();
 
-            let conn = events::establish_connection();
-            events::save_events(&conn, time_events);
-            let events = events::get_events(&conn);
+    let conn = events::establish_connection();
+    if let Some(report) = events::find_import(&conn, &upload) {
+        return Ok(Response::with((status::Ok, serde_json::to_string(&report).unwrap())));
+    }
 
-            let worksheet = worksheets::derive_work_sheet(events);
+    let last_run_nr = events::get_last_run_nr(&conn, &file.terminal);
 
-            Ok(Response::with((status::Ok, serde_json::to_string(&worksheet).unwrap())))
+    match db_parser::parse_db(file.path, last_run_nr) {
+        Ok(parsed) => {
+            let report = events::import(&conn, &upload, parsed);
+
+            Ok(Response::with((status::Ok, serde_json::to_string(&report).unwrap())))
         }
-        _ => {
-            Ok(Response::with((status::BadRequest, "Nope")))
+        Err(error) => {
+            let error = format!("Could not read uploaded database: {}", error);
+            events::record_failed_import(&conn, &upload, &error);
+
+            Ok(Response::with((status::UnprocessableEntity, error)))
         }
     }
-}
\ No newline at end of file
+}
+
+fn stage_entries(entries: Entries, settings: &worksheets::Settings) -> IronResult<Response> {
+    let file = match read_upload(&entries) {
+        Ok(file) => file,
+        Err(response) => return Ok(response),
+    };
+
+    let conn = events::establish_connection();
+    let last_run_nr = events::get_last_run_nr(&conn, &file.terminal);
+
+    match db_parser::parse_db(file.path, last_run_nr) {
+        Ok(parsed) => {
+            let staged_id = events::stage_import(&conn, &file.upload(), last_run_nr, &parsed);
+
+            preview_response(&conn, staged_id, settings)
+        }
+        Err(error) => {
+            Ok(Response::with((status::UnprocessableEntity, format!("Could not read uploaded database: {}", error))))
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct StagedPreview {
+    diff: events::ImportDiff,
+    // The affected days as they would look after committing
+    days: BTreeMap<chrono::NaiveDate, worksheets::WorkDay>,
+}
+
+fn preview_response(conn: &diesel::pg::PgConnection, staged_id: uuid::Uuid, settings: &worksheets::Settings) -> IronResult<Response> {
+    match events::preview_staged(conn, staged_id) {
+        Ok(preview) => {
+            let days = worksheets::derive_days(preview.events, &preview.master_data, &preview.diff.punches, settings);
+            let json = serde_json::to_string(&StagedPreview { diff: preview.diff, days }).unwrap();
+
+            Ok(Response::with((status::Ok, json)))
+        }
+        Err(error) => Ok(staging_error_response(error)),
+    }
+}
+
+fn staging_error_response(error: events::StagingError) -> Response {
+    match error {
+        events::StagingError::UnknownStagedImport(_) => Response::with((status::NotFound, error.to_string())),
+        events::StagingError::Stale { .. } => Response::with((status::Conflict, error.to_string())),
+        events::StagingError::Database(_) => Response::with((status::InternalServerError, error.to_string())),
+    }
+}
+
+fn staged_id(request: &Request) -> Option<uuid::Uuid> {
+    request.extensions
+        .get::<router::Router>()
+        .and_then(|params| params.find("id"))
+        .and_then(|id| uuid::Uuid::parse_str(id).ok())
+}
+
+fn get_staged_imports(_request: &mut Request) -> IronResult<Response> {
+    let conn = events::establish_connection();
+    let staged = events::get_staged_imports(&conn);
+
+    Ok(Response::with((status::Ok, serde_json::to_string(&staged).unwrap())))
+}
+
+fn get_staged_import(request: &mut Request) -> IronResult<Response> {
+    match staged_id(request) {
+        Some(staged_id) => preview_response(&events::establish_connection(), staged_id, &shared_settings(request)),
+        None => Ok(Response::with((status::BadRequest, "Incorrect staged import id submitted"))),
+    }
+}
+
+fn commit_staged_import(request: &mut Request) -> IronResult<Response> {
+    match staged_id(request) {
+        Some(staged_id) => match events::commit_staged(&events::establish_connection(), staged_id) {
+            Ok(report) => Ok(Response::with((status::Ok, serde_json::to_string(&report).unwrap()))),
+            Err(error) => Ok(staging_error_response(error)),
+        },
+        None => Ok(Response::with((status::BadRequest, "Incorrect staged import id submitted"))),
+    }
+}
+
+fn discard_staged_import(request: &mut Request) -> IronResult<Response> {
+    match staged_id(request) {
+        Some(staged_id) => match events::discard_staged(&events::establish_connection(), staged_id) {
+            Ok(()) => Ok(Response::with(status::NoContent)),
+            Err(error) => Ok(staging_error_response(error)),
+        },
+        None => Ok(Response::with((status::BadRequest, "Incorrect staged import id submitted"))),
+    }
+}
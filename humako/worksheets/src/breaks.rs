@@ -0,0 +1,190 @@
+use std::cmp;
+
+use chrono::{Duration, NaiveDateTime};
+
+use crate::work_sheet::{Segment, SegmentKind};
+
+// The Working Hours Act: at least `minutes` of break once an employee works
+// more than `after_minutes` on a day.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MinimumBreak {
+    pub after_minutes: i64,
+    pub minutes: i64,
+}
+
+// Without any rule set, breaks are exactly what was punched.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BreakPolicy {
+    // Shorter breaks are paid, they count for the action they interrupted
+    pub paid_below_minutes: Option<i64>,
+    // What is missing is deducted from the work right after `after_minutes`
+    pub minimum: Option<MinimumBreak>,
+    // The rest of a longer break is nobody's time
+    pub maximum_minutes: Option<i64>,
+    // Report the break total of a day in the second version of the API
+    pub line_item: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum AppliedBreakRule {
+    Paid { at: NaiveDateTime, seconds: i64 },
+    Capped { at: NaiveDateTime, seconds: i64 },
+    Deducted { at: NaiveDateTime, seconds: i64 },
+}
+
+impl BreakPolicy {
+    // The segments of one employee on one day, in order.
+    pub fn apply(&self, segments: Vec<Segment>) -> (Vec<Segment>, Vec<AppliedBreakRule>) {
+        let mut applied = vec![];
+
+        let mut segments = self.cap(segments, &mut applied);
+        self.pay(&mut segments, &mut applied);
+        let segments = self.deduct(segments, &mut applied);
+
+        (segments, applied)
+    }
+
+    fn cap(&self, segments: Vec<Segment>, applied: &mut Vec<AppliedBreakRule>) -> Vec<Segment> {
+        let maximum = match self.maximum_minutes {
+            Some(maximum) => Duration::minutes(maximum),
+            None => return segments,
+        };
+
+        segments
+            .into_iter()
+            .flat_map(|segment| {
+                if segment.kind != SegmentKind::Break || segment.end - segment.start <= maximum {
+                    return vec![segment];
+                }
+
+                let cut = segment.start + maximum;
+                applied.push(AppliedBreakRule::Capped { at: cut, seconds: (segment.end - cut).num_seconds() });
+
+                vec![
+                    Segment::new(SegmentKind::Break, None, segment.start, cut),
+                    Segment::new(SegmentKind::Unassigned, None, cut, segment.end),
+                ]
+            })
+            .collect()
+    }
+
+    fn pay(&self, segments: &mut [Segment], applied: &mut Vec<AppliedBreakRule>) {
+        let below = match self.paid_below_minutes {
+            Some(below) => below * 60,
+            None => return,
+        };
+
+        for i in 0..segments.len() {
+            if segments[i].kind != SegmentKind::Break || segments[i].seconds >= below {
+                continue;
+            }
+
+            let interrupted = segments[i + 1..]
+                .iter()
+                .find(|segment| segment.kind != SegmentKind::Break)
+                .filter(|segment| segment.kind == SegmentKind::Work)
+                .and_then(|segment| segment.action.clone());
+            // Without work after it there is no action to pay the break for
+            let interrupted = match interrupted {
+                Some(interrupted) => interrupted,
+                None => continue,
+            };
+
+            let segment = &mut segments[i];
+            segment.paid = true;
+            segment.action = Some(interrupted);
+            applied.push(AppliedBreakRule::Paid { at: segment.start, seconds: segment.seconds });
+        }
+    }
+
+    fn deduct(&self, segments: Vec<Segment>, applied: &mut Vec<AppliedBreakRule>) -> Vec<Segment> {
+        let minimum = match self.minimum {
+            Some(minimum) => minimum,
+            None => return segments,
+        };
+
+        let threshold = minimum.after_minutes * 60;
+        let worked: i64 = segments
+            .iter()
+            .filter(|segment| segment.credited_action().is_some())
+            .map(|segment| segment.seconds)
+            .sum();
+        let taken: i64 = segments
+            .iter()
+            .filter(|segment| segment.kind == SegmentKind::Break && !segment.paid)
+            .map(|segment| segment.seconds)
+            .sum();
+        let mut shortfall = minimum.minutes * 60 - taken;
+
+        if worked <= threshold || shortfall <= 0 {
+            return segments;
+        }
+
+        let mut worked_so_far = 0;
+        let mut deducted = vec![];
+        for segment in segments {
+            if segment.kind != SegmentKind::Work || shortfall == 0 {
+                if segment.credited_action().is_some() {
+                    worked_so_far += segment.seconds;
+                }
+                deducted.push(segment);
+                continue;
+            }
+
+            let mut start = segment.start;
+            if worked_so_far < threshold {
+                let until = threshold - worked_so_far;
+                if segment.seconds <= until {
+                    worked_so_far += segment.seconds;
+                    deducted.push(segment);
+                    continue;
+                }
+
+                start = segment.start + Duration::seconds(until);
+                worked_so_far = threshold;
+                deducted.push(Segment::new(SegmentKind::Work, segment.action.clone(), segment.start, start));
+            }
+
+            let seconds = cmp::min((segment.end - start).num_seconds(), shortfall);
+            let end = start + Duration::seconds(seconds);
+            applied.push(AppliedBreakRule::Deducted { at: start, seconds });
+            shortfall -= seconds;
+
+            deducted.push(Segment::new(SegmentKind::Break, None, start, end));
+            if end < segment.end {
+                deducted.push(Segment::new(SegmentKind::Work, segment.action.clone(), end, segment.end));
+            }
+        }
+
+        deducted
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDateTime;
+
+    use super::*;
+
+    #[test]
+    fn it_should_only_pay_a_break_that_interrupted_an_action() {
+        let at = |moment: &str| NaiveDateTime::parse_from_str(moment, "%Y-%m-%dT%H:%M:%S").unwrap();
+        let policy = BreakPolicy { paid_below_minutes: Some(15), ..BreakPolicy::default() };
+        let segments = vec![
+            Segment::new(SegmentKind::Work, Some("Kas".to_string()), at("2019-01-02T07:00:00"), at("2019-01-02T10:00:00")),
+            Segment::new(SegmentKind::Break, None, at("2019-01-02T10:00:00"), at("2019-01-02T10:10:00")),
+            Segment::new(SegmentKind::Work, Some("Kas".to_string()), at("2019-01-02T10:10:00"), at("2019-01-02T12:00:00")),
+            Segment::new(SegmentKind::Break, None, at("2019-01-02T12:00:00"), at("2019-01-02T12:10:00")),
+            Segment::new(SegmentKind::Unassigned, None, at("2019-01-02T12:10:00"), at("2019-01-02T13:00:00")),
+        ];
+
+        let (segments, applied) = policy.apply(segments);
+
+        assert_eq!(segments[1].credited_action(), Some(&"Kas".to_string()));
+        assert!(!segments[3].paid);
+        assert_eq!(applied, vec![AppliedBreakRule::Paid { at: at("2019-01-02T10:00:00"), seconds: 10 * 60 }]);
+    }
+}
@@ -1,25 +1,83 @@
 extern crate serde_json;
 
+#[macro_use]
 extern crate serde_derive;
 
-use std::collections::HashMap;
+mod breaks;
+mod period;
+mod roles;
+mod rounding;
+mod settings;
+mod shifts;
+mod work_sheet;
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use events::models::Event;
-use db_parser::TimeRowEvent;
+use db_parser::{MasterDataPerSource, TimeRowEvent};
 use chrono::NaiveDate;
 use chrono::NaiveDateTime;
+use chrono::NaiveTime;
 
-pub fn derive_work_sheet(events: Vec<Event>) -> HashMap<NaiveDate, HashMap<String, HashMap<String, i32>>> {
-    let mut days: HashMap<NaiveDate, HashMap<String, HashMap<String, i32>>> = HashMap::new();
+// Employees are grouped on their id, two employees can share a name. Events
+// imported before ids were kept can only be grouped on name.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum EmployeeKey {
+    Id(u32),
+    Name(String),
+}
 
-    let mut time_entries_per_employee: HashMap<String, Vec<TimeRowEvent>> = events
-        .iter()
-        .map(|event| {
-            let time_entry: TimeRowEvent = serde_json::from_str(&event.payload).expect("parsing failed");
-            return time_entry;
+pub use self::breaks::{AppliedBreakRule, BreakPolicy, MinimumBreak};
+pub use self::period::{EmployeeTotals, Period, PeriodKind, PeriodSettings, PeriodTotals};
+pub use self::roles::{ActionRole, PunchSemantics, Role};
+pub use self::rounding::{Rounding, RoundingPolicy, RoundingScope};
+pub use self::settings::{Settings, SettingsError, Site};
+pub use self::shifts::ShiftPolicy;
+pub use self::work_sheet::{ActionTotal, BreakTotal, DayTotals, EmployeeDay, Segment, SegmentKind, WorkDay, WorkSheet};
+
+// Punches of one employee closer together than this were most likely made by
+// pressing twice.
+const DUPLICATE_PUNCH_SECONDS: i64 = 60;
+
+// Situations the work sheet can't be trusted on, they are reported per
+// employee per day until a correction resolves them.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Anomaly {
+    // The day did not start with a clock-in, the first action is not counted
+    MissingClockIn { at: NaiveDateTime, action: String },
+    OpenBreak { since: NaiveDateTime },
+    SinglePunch { at: NaiveDateTime },
+    DuplicatePunch { at: NaiveDateTime, seconds_apart: i64 },
+    NegativeDuration { at: NaiveDateTime, action: String, minutes: i64 },
+}
+
+type Anomalies = HashMap<NaiveDate, HashMap<String, Vec<Anomaly>>>;
+type Segments = HashMap<NaiveDate, HashMap<String, Vec<Segment>>>;
+type TimeEntries = HashMap<EmployeeKey, Vec<(String, TimeRowEvent)>>;
+
+// With `as_of` the work sheet is derived from only the events imported up to
+// that moment, so the events may hold every revision.
+pub fn derive_work_sheet(events: Vec<Event>, master_data: &MasterDataPerSource, as_of: Option<NaiveDateTime>, settings: &Settings) -> WorkSheet {
+    work_sheet_of(time_entries_per_employee(events, master_data, as_of), settings)
+}
+
+// Every punch with the terminal it was punched at, per employee in order. The
+// names are those the terminal knows the ids by.
+fn time_entries_per_employee(events: Vec<Event>, master_data: &MasterDataPerSource, as_of: Option<NaiveDateTime>) -> TimeEntries {
+    let known: Vec<Event> = events
+        .into_iter()
+        .filter(|event| as_of.map(|as_of| event.timestamp <= as_of).unwrap_or(true))
+        .collect();
+
+    let mut time_entries_per_employee: TimeEntries = events::corrected_time_rows(&events::drop_voided(events::latest_revisions(known)))
+        .into_iter()
+        .map(|(terminal, mut time_entry)| {
+            master_data.resolve(&terminal, &mut time_entry);
+            (terminal, time_entry)
         })
-        .fold(HashMap::new(), |mut map, time_entry: TimeRowEvent| {
-            let rows = map.entry(time_entry.employee.clone()).or_insert(vec![]);
-            rows.push(time_entry);
+        .fold(HashMap::new(), |mut map, (terminal, time_entry): (String, TimeRowEvent)| {
+            let rows = map.entry(employee_key(&time_entry)).or_default();
+            rows.push((terminal, time_entry));
 
             map
         });
@@ -27,15 +85,44 @@ pub fn derive_work_sheet(events: Vec<Event>) -> HashMap<NaiveDate, HashMap<Strin
     time_entries_per_employee
         .iter_mut()
         .for_each(|(_employee, entries)| {
-            entries.sort_by(|a, b| {
+            entries.sort_by(|(_, a), (_, b)| {
                 a.timestamp.cmp(&b.timestamp)
             })
         });
 
+    time_entries_per_employee
+}
+
+fn employee_key(time_entry: &TimeRowEvent) -> EmployeeKey {
+    match time_entry.employee_id {
+        Some(id) => EmployeeKey::Id(id),
+        None => EmployeeKey::Name(time_entry.employee.clone()),
+    }
+}
+
+// The work day of every punch of one employee.
+fn work_days(settings: &Settings, time_entries: &[(String, TimeRowEvent)]) -> Vec<NaiveDate> {
+    let punches: Vec<(NaiveDateTime, Option<NaiveTime>)> = time_entries
+        .iter()
+        .map(|(terminal, time_row)| (time_row.timestamp, settings.day_start(terminal)))
+        .collect();
+
+    settings.shifts.work_days(&punches)
+}
+
+fn work_sheet_of(time_entries_per_employee: TimeEntries, settings: &Settings) -> WorkSheet {
+    let mut segments: Segments = HashMap::new();
+    let mut anomalies: Anomalies = HashMap::new();
+
+    let labels = employee_labels(&time_entries_per_employee);
+
     let mut last_action: Option<String> = None;
     let mut last_date: Option<NaiveDateTime> = None;
     let mut last_break_date: Option<NaiveDateTime> = None;
-    let mut break_time: i32 = 0;
+    // Work segments wait for the punch that closes them to know their action
+    let mut open_work: Vec<Segment> = vec![];
+    // The action a terminal with the action at the start was last punched for
+    let mut started_action: Option<String> = None;
 
     time_entries_per_employee
         .iter()
@@ -43,83 +130,302 @@ pub fn derive_work_sheet(events: Vec<Event>) -> HashMap<NaiveDate, HashMap<Strin
             last_date = None;
             last_action = None;
             last_break_date = None;
-            break_time = 0;
+            started_action = None;
+
+            let label = &labels[employee];
+            // The punch with the work day it belongs to
+            let mut previous_punch: Option<(NaiveDateTime, NaiveDate)> = None;
+            let mut punches_on_day = 0;
 
             time_entries
                 .iter()
-                .for_each(|time_row| {
+                .zip(work_days(settings, time_entries))
+                .for_each(|((terminal, time_row), day)| {
+                    let semantics = settings.semantics(terminal);
+                    let previous_day = previous_punch.map(|(_, previous_day)| previous_day);
+                    match previous_punch {
+                        Some((previous, previous_day)) if previous_day == day => {
+                            let seconds_apart = (time_row.timestamp - previous).num_seconds();
+                            if seconds_apart < DUPLICATE_PUNCH_SECONDS {
+                                report(&mut anomalies, day, label, Anomaly::DuplicatePunch { at: time_row.timestamp, seconds_apart });
+                            }
+                        }
+                        Some((previous, previous_day)) => {
+                            close_day(&mut anomalies, previous_day, label, previous, punches_on_day, last_break_date);
+                            punches_on_day = 0;
+                        }
+                        None => {}
+                    }
+                    let role = settings.role(time_row);
+                    let starts_action = role == Role::Work && semantics == PunchSemantics::ActionAtStart;
+                    if punches_on_day == 0 && !role.clocks_in() && !starts_action {
+                        report(&mut anomalies, day, label, Anomaly::MissingClockIn { at: time_row.timestamp, action: time_row.action.clone() });
+                    }
+                    previous_punch = Some((time_row.timestamp, day));
+                    punches_on_day += 1;
+
                     match last_date {
                         Some(datetime) => {
-                            if datetime.date() != time_row.timestamp.date() {
+                            if let Some(previous_day) = previous_day.filter(|previous_day| *previous_day != day) {
                                 // New day
+                                close_work(&mut segments, previous_day, label, &mut open_work, started_action.as_ref());
                                 last_action = None;
                                 last_break_date = None;
+                                started_action = None;
                             }
 
-                            if time_row.action == "Begin/Pauze" {
+                            if role == Role::EndOfDay {
+                                // End of day, the time since the last action is nobody's
+                                if last_action.is_some() && last_break_date.is_none() {
+                                    open_work.push(Segment::new(SegmentKind::Unassigned, None, datetime, time_row.timestamp));
+                                }
+                                close_work(&mut segments, day, label, &mut open_work, started_action.as_ref());
+                                last_action = None;
+                                last_break_date = None;
+                                started_action = None;
+                            } else if role.clocks_in() {
                                 match last_break_date {
                                     Some(break_date) => {
                                         // End of break
-                                        let diff = time_row.timestamp.timestamp_millis() - break_date.timestamp_millis();
-                                        let diff = diff / 1000 / 60;
-
-                                        break_time += diff as i32;
+                                        add_segment(&mut segments, day, label, Segment::new(SegmentKind::Break, None, break_date, time_row.timestamp));
+                                        last_date = Some(time_row.timestamp);
                                         last_break_date = None;
                                     }
+                                    None if last_action.is_some() && role == Role::StartOfDay => {
+                                        // Clocking in twice changes nothing
+                                    }
                                     None if last_action.is_some() => {
                                         // Start of break
-                                        last_break_date = Some(time_row.timestamp.clone());
+                                        open_work.push(Segment::new(SegmentKind::Unassigned, None, datetime, time_row.timestamp));
+                                        if started_action.is_some() {
+                                            close_work(&mut segments, day, label, &mut open_work, started_action.as_ref());
+                                        }
+                                        last_break_date = Some(time_row.timestamp);
                                     }
                                     None => {
                                         // Start of new action in new day
-                                        break_time = 0;
-                                        last_date = Some(time_row.timestamp.clone());
+                                        last_date = Some(time_row.timestamp);
                                         last_action = Some(time_row.action.clone());
                                     }
                                 }
+                            } else if starts_action {
+                                // Start of action, the action before it ends
+                                if last_action.is_some() {
+                                    let start = last_break_date.unwrap_or(datetime);
+                                    open_work.push(Segment::new(SegmentKind::Unassigned, None, start, time_row.timestamp));
+                                    close_work(&mut segments, day, label, &mut open_work, started_action.as_ref());
+                                }
+
+                                started_action = Some(time_row.action.clone());
+                                last_date = Some(time_row.timestamp);
+                                last_action = Some(time_row.action.clone());
+                                last_break_date = None;
                             } else {
                                 // End of action
-                                let day = days
-                                    .entry(datetime.date())
-                                    .or_insert(HashMap::new());
-
-                                let employee_map = day
-                                    .entry(employee.clone())
-                                    .or_insert(HashMap::new());
-
-                                let action_to_mutate = employee_map
-                                    .entry(time_row.action.clone())
-                                    .or_insert(0);
-
                                 match &last_action {
                                     Some(_action) => {
-                                        let diff = time_row.timestamp.timestamp_millis() - datetime.timestamp_millis();
-                                        let diff = diff / 1000 / 60;
-                                        let diff_minus_break = diff - break_time as i64;
-                                        *action_to_mutate += diff_minus_break as i32;
-                                        break_time = 0;
+                                        // A break nobody punched the end of ends with the action
+                                        let start = last_break_date.unwrap_or(datetime);
+                                        open_work.push(Segment::new(SegmentKind::Unassigned, None, start, time_row.timestamp));
+                                        close_work(&mut segments, day, label, &mut open_work, Some(&time_row.action));
                                         last_break_date = None;
                                     }
                                     _ => {
-                                        // First action of the day was not "Begin/Pauze"
+                                        // First action of the day was not a clock-in
                                         // Should be fixed in source database
                                     }
                                 }
 
-                                last_date = Some(time_row.timestamp.clone());
+                                started_action = None;
+                                last_date = Some(time_row.timestamp);
                                 last_action = Some(time_row.action.clone());
                             }
                         }
+                        None if role == Role::EndOfDay => {}
                         None => {
                             // Start of day
-                            last_date = Some(time_row.timestamp.clone());
+                            if starts_action {
+                                started_action = Some(time_row.action.clone());
+                            }
+                            last_date = Some(time_row.timestamp);
                             last_action = Some(time_row.action.clone());
                         }
                     }
                 });
+
+            if let Some((previous, previous_day)) = previous_punch {
+                close_work(&mut segments, previous_day, label, &mut open_work, started_action.as_ref());
+                close_day(&mut anomalies, previous_day, label, previous, punches_on_day, last_break_date);
+            }
         });
 
-    days
+    let employees: HashMap<&String, (Option<u32>, String)> = labels
+        .iter()
+        .map(|(key, label)| {
+            let employee_id = match key {
+                EmployeeKey::Id(id) => Some(*id),
+                EmployeeKey::Name(_) => None,
+            };
+
+            (label, (employee_id, time_entries_per_employee[key][0].1.employee.clone()))
+        })
+        .collect();
+
+    let mut work_sheet = WorkSheet::default();
+    segments.into_iter().for_each(|(date, day)| {
+        day.into_iter().for_each(|(label, mut list)| {
+            list.sort_by_key(|segment| segment.start);
+            let (list, break_rules) = settings.breaks.apply(list);
+            check_durations(&mut anomalies, date, &label, &list);
+
+            let employee_day = employee_day(&mut work_sheet, date, &label, &employees[&label]);
+            list.into_iter().for_each(|segment| employee_day.add_segment(segment));
+            employee_day.break_rules = break_rules;
+        })
+    });
+    anomalies.into_iter().for_each(|(date, day)| {
+        day.into_iter().for_each(|(label, list)| {
+            employee_day(&mut work_sheet, date, &label, &employees[&label]).anomalies = list;
+        })
+    });
+    work_sheet.summarize(settings);
+
+    work_sheet
+}
+
+fn add_segment(segments: &mut Segments, day: NaiveDate, employee: &str, segment: Segment) {
+    segments
+        .entry(day)
+        .or_default()
+        .entry(employee.to_string())
+        .or_default()
+        .push(segment);
+}
+
+// Work nobody said the action of stays unassigned.
+fn close_work(segments: &mut Segments, day: NaiveDate, employee: &str, open_work: &mut Vec<Segment>, action: Option<&String>) {
+    open_work.drain(..).for_each(|mut segment| {
+        if let Some(action) = action {
+            segment.kind = SegmentKind::Work;
+            segment.action = Some(action.clone());
+        }
+        add_segment(segments, day, employee, segment);
+    });
+}
+
+fn employee_day<'a>(work_sheet: &'a mut WorkSheet, date: NaiveDate, label: &str, employee: &(Option<u32>, String)) -> &'a mut EmployeeDay {
+    work_sheet.days
+        .entry(date)
+        .or_insert_with(|| WorkDay::new(date))
+        .employees
+        .entry(label.to_string())
+        .or_insert_with(|| EmployeeDay::new(employee.0, employee.1.clone()))
+}
+
+fn report(anomalies: &mut Anomalies, day: NaiveDate, employee: &str, anomaly: Anomaly) {
+    anomalies
+        .entry(day)
+        .or_default()
+        .entry(employee.to_string())
+        .or_default()
+        .push(anomaly);
+}
+
+fn close_day(anomalies: &mut Anomalies, day: NaiveDate, employee: &str, last_punch: NaiveDateTime, punches_on_day: usize, open_break: Option<NaiveDateTime>) {
+    if punches_on_day == 1 {
+        report(anomalies, day, employee, Anomaly::SinglePunch { at: last_punch });
+    }
+
+    if let Some(since) = open_break {
+        report(anomalies, day, employee, Anomaly::OpenBreak { since });
+    }
+}
+
+// Sorted punches and a break policy that only cuts segments up should never
+// leave one negative, this guards the totals against a policy that does.
+fn check_durations(anomalies: &mut Anomalies, day: NaiveDate, employee: &str, segments: &[Segment]) {
+    segments
+        .iter()
+        .filter(|segment| segment.seconds < 0)
+        .for_each(|segment| {
+            let action = segment.action.clone().unwrap_or_default();
+            report(anomalies, day, employee, Anomaly::NegativeDuration { at: segment.start, action, minutes: segment.seconds / 60 });
+        });
+}
+
+// The work days the given punches fall on, bucketed like the work sheet
+// buckets them. A punch that is not stored (any more) gets the work day it
+// would fall on among the punches of its employee. Days without any punches
+// left, after an import was reverted, come back empty.
+pub fn derive_days(events: Vec<Event>, master_data: &MasterDataPerSource, punches: &[(String, TimeRowEvent)], settings: &Settings) -> BTreeMap<NaiveDate, WorkDay> {
+    let time_entries_per_employee = time_entries_per_employee(events, master_data, None);
+
+    let touched: TimeEntries = punches
+        .iter()
+        .map(|(terminal, time_row)| {
+            let mut time_row = time_row.clone();
+            master_data.resolve(terminal, &mut time_row);
+
+            (terminal.clone(), time_row)
+        })
+        .fold(HashMap::new(), |mut map, (terminal, time_row)| {
+            map.entry(employee_key(&time_row)).or_insert_with(Vec::new).push((terminal, time_row));
+
+            map
+        });
+    let same_punch = |(terminal, time_row): &(String, TimeRowEvent), (other_terminal, other): &(String, TimeRowEvent)| {
+        terminal == other_terminal && time_row.id == other.id && time_row.timestamp == other.timestamp
+    };
+
+    let days: BTreeSet<NaiveDate> = touched
+        .into_iter()
+        .flat_map(|(employee, punches)| {
+            let mut entries = time_entries_per_employee.get(&employee).cloned().unwrap_or_default();
+            for punch in &punches {
+                if !entries.iter().any(|entry| same_punch(entry, punch)) {
+                    entries.push(punch.clone());
+                }
+            }
+            entries.sort_by_key(|(_, time_row)| time_row.timestamp);
+            let work_days = work_days(settings, &entries);
+
+            punches
+                .iter()
+                .filter_map(|punch| entries.iter().position(|entry| same_punch(entry, punch)))
+                .map(|position| work_days[position])
+                .collect::<Vec<_>>()
+        })
+        .collect();
+
+    let mut work_sheet = work_sheet_of(time_entries_per_employee, settings);
+
+    days.into_iter()
+        .map(|day| (day, work_sheet.days.remove(&day).unwrap_or_else(|| WorkDay::new(day))))
+        .collect()
+}
+
+// The work sheet is keyed on name, a name shared by several employees gets
+// their id appended.
+fn employee_labels(employees: &HashMap<EmployeeKey, Vec<(String, TimeRowEvent)>>) -> HashMap<EmployeeKey, String> {
+    let names: HashMap<&EmployeeKey, &str> = employees
+        .iter()
+        .filter_map(|(key, rows)| rows.first().map(|(_, row)| (key, row.employee.as_str())))
+        .collect();
+
+    let mut name_count: HashMap<&str, usize> = HashMap::new();
+    names.values().for_each(|name| *name_count.entry(name).or_insert(0) += 1);
+
+    names
+        .iter()
+        .map(|(key, name)| {
+            let label = match key {
+                EmployeeKey::Id(id) if name_count[name] > 1 => format!("{} ({})", name, id),
+                _ => name.to_string(),
+            };
+
+            ((*key).clone(), label)
+        })
+        .collect()
 }
 
 
@@ -127,93 +433,71 @@ pub fn derive_work_sheet(events: Vec<Event>) -> HashMap<NaiveDate, HashMap<Strin
 mod tests {
     use events::models::Event;
     use chrono::NaiveDateTime;
-    use std::collections::HashMap;
-    use chrono::NaiveDate;
+    use std::collections::{BTreeMap, HashMap};
+    use chrono::{NaiveDate, NaiveTime};
+    use db_parser::{MasterData, MasterDataPerSource, TimeRowEvent};
+    use crate::{ActionRole, AppliedBreakRule, BreakPolicy, BreakTotal, DayTotals, MinimumBreak, Period, PeriodKind, PunchSemantics, Role, Segment, SegmentKind, Settings, ShiftPolicy, Site};
+
+    fn punch(source: &str, unique_id: i32, employee_id: u32, action_id: u32, timestamp: &str) -> Event {
+        let payload = format!(
+            "{{\"id\":{},\"employee_id\":{},\"employee\":\"Onbekend\",\"action_id\":{},\"action\":\"Onbekend\",\"timestamp\":\"{}\"}}",
+            unique_id, employee_id, action_id, timestamp
+        );
+
+        Event::new("time_row_event", source, unique_id, payload)
+    }
+
+    fn minutes(work_sheet: &crate::WorkSheet, day: NaiveDate, employee: &str, action: &str) -> Option<i32> {
+        work_sheet.day(&day).and_then(|day| day.employee(employee)).and_then(|employee| employee.minutes(action))
+    }
+
+    // The same ids and names at every terminal the tests punch at
+    fn master_data() -> MasterDataPerSource {
+        let mut terminal = MasterData::default();
+        terminal.employees.insert(7, "Jan".to_string());
+        terminal.employees.insert(8, "Piet".to_string());
+        terminal.actions.insert(1, "Begin/Pauze".to_string());
+        terminal.actions.insert(2, "Kas".to_string());
+        terminal.actions.insert(3, "toppen B".to_string());
+
+        let mut master_data = MasterDataPerSource::default();
+        for source in &["default", "kas-noord", "kas-zuid", "kas-nieuw"] {
+            *master_data.source_mut(source) = terminal.clone();
+        }
+
+        master_data
+    }
 
     #[test]
     fn it_should_convert_events_to_work_sheet() {
         // Arrange
         let events: Vec<Event> = vec![
-            Event {
-                id: uuid::Uuid::new_v4(),
-                unique_id: 173,
-                event_type: "time_row_event".to_string(),
-                payload: "{\"id\":173,\"employee\":\"Michel\",\"action\":\"Begin/Pauze\",\"timestamp\":\"2019-01-02T07:01:16\"}".to_string(),
-                timestamp: NaiveDateTime::parse_from_str("2019-01-11T15:33:43.390750", "%Y-%m-%dT%H:%M:%S%.f").unwrap(),
-            },
-            Event {
-                id: uuid::Uuid::new_v4(),
-                unique_id: 188,
-                event_type: "time_row_event".to_string(),
-                payload: "{\"id\":188,\"employee\":\"Michel\",\"action\":\"Kas\",\"timestamp\":\"2019-01-02T08:59:42\"}".to_string(),
-                timestamp: NaiveDateTime::parse_from_str("2019-01-11T15:33:43.390750", "%Y-%m-%dT%H:%M:%S%.f").unwrap(),
-            },
-            Event {
-                id: uuid::Uuid::new_v4(),
-                unique_id: 209,
-                event_type: "time_row_event".to_string(),
-                payload: "{\"id\":209,\"employee\":\"Michel\",\"action\":\"Begin/Pauze\",\"timestamp\":\"2019-01-02T10:03:00\"}".to_string(),
-                timestamp: NaiveDateTime::parse_from_str("2019-01-11T15:33:43.390750", "%Y-%m-%dT%H:%M:%S%.f").unwrap(),
-            },
-            Event {
-                id: uuid::Uuid::new_v4(),
-                unique_id: 216,
-                event_type: "time_row_event".to_string(),
-                payload: "{\"id\":216,\"employee\":\"Michel\",\"action\":\"Begin/Pauze\",\"timestamp\":\"2019-01-02T10:19:06\"}".to_string(),
-                timestamp: NaiveDateTime::parse_from_str("2019-01-11T15:33:43.390750", "%Y-%m-%dT%H:%M:%S%.f").unwrap(),
-            },
-            Event {
-                id: uuid::Uuid::new_v4(),
-                unique_id: 236,
-                event_type: "time_row_event".to_string(),
-                payload: "{\"id\":236,\"employee\":\"Michel\",\"action\":\"toppen B\",\"timestamp\":\"2019-01-02T10:25:22\"}".to_string(),
-                timestamp: NaiveDateTime::parse_from_str("2019-01-11T15:33:43.390750", "%Y-%m-%dT%H:%M:%S%.f").unwrap(),
-            },
-            Event {
-                id: uuid::Uuid::new_v4(),
-                unique_id: 264,
-                event_type: "time_row_event".to_string(),
-                payload: "{\"id\":264,\"employee\":\"Michel\",\"action\":\"Begin/Pauze\",\"timestamp\":\"2019-01-02T12:50:44\"}".to_string(),
-                timestamp: NaiveDateTime::parse_from_str("2019-01-11T15:33:43.390750", "%Y-%m-%dT%H:%M:%S%.f").unwrap(),
-            },
-            Event {
-                id: uuid::Uuid::new_v4(),
-                unique_id: 264,
-                event_type: "time_row_event".to_string(),
-                payload: "{\"id\":264,\"employee\":\"Michel\",\"action\":\"Begin/Pauze\",\"timestamp\":\"2019-01-02T13:19:44\"}".to_string(),
-                timestamp: NaiveDateTime::parse_from_str("2019-01-11T15:33:43.390750", "%Y-%m-%dT%H:%M:%S%.f").unwrap(),
-            },
-            Event {
-                id: uuid::Uuid::new_v4(),
-                unique_id: 272,
-                event_type: "time_row_event".to_string(),
-                payload: "{\"id\":272,\"employee\":\"Michel\",\"action\":\"steken B\",\"timestamp\":\"2019-01-02T13:22:30\"}".to_string(),
-                timestamp: NaiveDateTime::parse_from_str("2019-01-11T15:33:43.390750", "%Y-%m-%dT%H:%M:%S%.f").unwrap(),
-            },
-            Event {
-                id: uuid::Uuid::new_v4(),
-                unique_id: 285,
-                event_type: "time_row_event".to_string(),
-                payload: "{\"id\":285,\"employee\":\"Michel\",\"action\":\"stek plukken B\",\"timestamp\":\"2019-01-02T15:54:29\"}".to_string(),
-                timestamp: NaiveDateTime::parse_from_str("2019-01-11T15:33:43.390750", "%Y-%m-%dT%H:%M:%S%.f").unwrap(),
-            },
+            Event::new("time_row_event", "default", 173, "{\"id\":173,\"employee\":\"Michel\",\"action\":\"Begin/Pauze\",\"timestamp\":\"2019-01-02T07:01:16\"}".to_string()),
+            Event::new("time_row_event", "default", 188, "{\"id\":188,\"employee\":\"Michel\",\"action\":\"Kas\",\"timestamp\":\"2019-01-02T08:59:42\"}".to_string()),
+            Event::new("time_row_event", "default", 209, "{\"id\":209,\"employee\":\"Michel\",\"action\":\"Begin/Pauze\",\"timestamp\":\"2019-01-02T10:03:00\"}".to_string()),
+            Event::new("time_row_event", "default", 216, "{\"id\":216,\"employee\":\"Michel\",\"action\":\"Begin/Pauze\",\"timestamp\":\"2019-01-02T10:19:06\"}".to_string()),
+            Event::new("time_row_event", "default", 236, "{\"id\":236,\"employee\":\"Michel\",\"action\":\"toppen B\",\"timestamp\":\"2019-01-02T10:25:22\"}".to_string()),
+            Event::new("time_row_event", "default", 264, "{\"id\":264,\"employee\":\"Michel\",\"action\":\"Begin/Pauze\",\"timestamp\":\"2019-01-02T12:50:44\"}".to_string()),
+            Event::new("time_row_event", "default", 264, "{\"id\":264,\"employee\":\"Michel\",\"action\":\"Begin/Pauze\",\"timestamp\":\"2019-01-02T13:19:44\"}".to_string()),
+            Event::new("time_row_event", "default", 272, "{\"id\":272,\"employee\":\"Michel\",\"action\":\"steken B\",\"timestamp\":\"2019-01-02T13:22:30\"}".to_string()),
+            Event::new("time_row_event", "default", 285, "{\"id\":285,\"employee\":\"Michel\",\"action\":\"stek plukken B\",\"timestamp\":\"2019-01-02T15:54:29\"}".to_string()),
         ];
-        let mut actions: HashMap<String, i32> = HashMap::new();
+        let mut actions: BTreeMap<String, i32> = BTreeMap::new();
         actions.insert("Kas".to_string(), 118);
         actions.insert("toppen B".to_string(), 69);
         actions.insert("steken B".to_string(), 148);
         actions.insert("stek plukken B".to_string(), 151);
 
         // Act
-        let work_sheet = crate::derive_work_sheet(events);
+        let work_sheet = crate::derive_work_sheet(events, &MasterDataPerSource::default(), None, &Settings::default());
         let result = work_sheet
-            .get(&NaiveDate::from_ymd(2019, 1, 2))
+            .day(&NaiveDate::from_ymd(2019, 1, 2))
             .unwrap()
-            .get("Michel")
+            .employee("Michel")
             .unwrap();
 
         // Assert
-        assert_eq!(result, &actions);
+        assert_eq!(result.minutes_per_action(), actions);
     }
 
     #[test]
@@ -221,71 +505,433 @@ mod tests {
 
         // Arrange
         let events: Vec<Event> = vec![
-            Event {
-                id: uuid::Uuid::new_v4(),
-                unique_id: 172,
-                event_type: "time_row_event".to_string(),
-                payload: "{\"id\":172,\"employee\":\"Michel\",\"action\":\"Begin/Pauze\",\"timestamp\":\"2019-01-02T07:01:07\"}".to_string(),
-                timestamp: NaiveDateTime::parse_from_str("2019-01-11T15:33:43.390750", "%Y-%m-%dT%H:%M:%S%.f").unwrap(),
-            },
-            Event {
-                id: uuid::Uuid::new_v4(),
-                unique_id: 210,
-                event_type: "time_row_event".to_string(),
-                payload: "{\"id\":210,\"employee\":\"Michel\",\"action\":\"Begin/Pauze\",\"timestamp\":\"2019-01-02T10:03:20\"}".to_string(),
-                timestamp: NaiveDateTime::parse_from_str("2019-01-11T15:33:43.390750", "%Y-%m-%dT%H:%M:%S%.f").unwrap(),
-            },
-            Event {
-                id: uuid::Uuid::new_v4(),
-                unique_id: 225,
-                event_type: "time_row_event".to_string(),
-                payload: "{\"id\":225,\"employee\":\"Michel\",\"action\":\"Begin/Pauze\",\"timestamp\":\"2019-01-02T10:20:19\"}".to_string(),
-                timestamp: NaiveDateTime::parse_from_str("2019-01-11T15:33:43.390750", "%Y-%m-%dT%H:%M:%S%.f").unwrap(),
-            },
-            Event {
-                id: uuid::Uuid::new_v4(),
-                unique_id: 254,
-                event_type: "time_row_event".to_string(),
-                payload: "{\"id\":254,\"employee\":\"Michel\",\"action\":\"Begin/Pauze\",\"timestamp\":\"2019-01-02T13:20:00\"}".to_string(),
-                timestamp: NaiveDateTime::parse_from_str("2019-01-11T15:33:43.390750", "%Y-%m-%dT%H:%M:%S%.f").unwrap(),
-            },
-            Event {
-                id: uuid::Uuid::new_v4(),
-                unique_id: 293,
-                event_type: "time_row_event".to_string(),
-                payload: "{\"id\":293,\"employee\":\"Michel\",\"action\":\"gewasverz opkw\",\"timestamp\":\"2019-01-02T16:09:17\"}".to_string(),
-                timestamp: NaiveDateTime::parse_from_str("2019-01-11T15:33:43.390750", "%Y-%m-%dT%H:%M:%S%.f").unwrap(),
-            },
-            Event {
-                id: uuid::Uuid::new_v4(),
-                unique_id: 294,
-                event_type: "time_row_event".to_string(),
-                payload: "{\"id\":294,\"employee\":\"Michel\",\"action\":\"opkweek divers B\",\"timestamp\":\"2019-01-02T16:18:22\"}".to_string(),
-                timestamp: NaiveDateTime::parse_from_str("2019-01-11T15:33:43.390750", "%Y-%m-%dT%H:%M:%S%.f").unwrap(),
-            },
-            Event {
-                id: uuid::Uuid::new_v4(),
-                unique_id: 404,
-                event_type: "time_row_event".to_string(),
-                payload: "{\"id\":404,\"employee\":\"Michel\",\"action\":\"Begin/Pauze\",\"timestamp\":\"2019-01-02T12:54:21\"}".to_string(),
-                timestamp: NaiveDateTime::parse_from_str("2019-01-11T15:33:43.390750", "%Y-%m-%dT%H:%M:%S%.f").unwrap(),
-            },
+            Event::new("time_row_event", "default", 172, "{\"id\":172,\"employee\":\"Michel\",\"action\":\"Begin/Pauze\",\"timestamp\":\"2019-01-02T07:01:07\"}".to_string()),
+            Event::new("time_row_event", "default", 210, "{\"id\":210,\"employee\":\"Michel\",\"action\":\"Begin/Pauze\",\"timestamp\":\"2019-01-02T10:03:20\"}".to_string()),
+            Event::new("time_row_event", "default", 225, "{\"id\":225,\"employee\":\"Michel\",\"action\":\"Begin/Pauze\",\"timestamp\":\"2019-01-02T10:20:19\"}".to_string()),
+            Event::new("time_row_event", "default", 254, "{\"id\":254,\"employee\":\"Michel\",\"action\":\"Begin/Pauze\",\"timestamp\":\"2019-01-02T13:20:00\"}".to_string()),
+            Event::new("time_row_event", "default", 293, "{\"id\":293,\"employee\":\"Michel\",\"action\":\"gewasverz opkw\",\"timestamp\":\"2019-01-02T16:09:17\"}".to_string()),
+            Event::new("time_row_event", "default", 294, "{\"id\":294,\"employee\":\"Michel\",\"action\":\"opkweek divers B\",\"timestamp\":\"2019-01-02T16:18:22\"}".to_string()),
+            Event::new("time_row_event", "default", 404, "{\"id\":404,\"employee\":\"Michel\",\"action\":\"Begin/Pauze\",\"timestamp\":\"2019-01-02T12:54:21\"}".to_string()),
         ];
 
         // Act
-        let mut actions: HashMap<String, i32> = HashMap::new();
-        actions.insert("gewasverz opkw".to_string(), 507);
+        let mut actions: BTreeMap<String, i32> = BTreeMap::new();
+        // 505m32s worked, breaks are no longer subtracted in whole minutes
+        actions.insert("gewasverz opkw".to_string(), 505);
         actions.insert("opkweek divers B".to_string(), 9);
 
         // Act
-        let work_sheet = crate::derive_work_sheet(events);
+        let work_sheet = crate::derive_work_sheet(events, &MasterDataPerSource::default(), None, &Settings::default());
+        let result = work_sheet
+            .day(&NaiveDate::from_ymd(2019, 1, 2))
+            .unwrap()
+            .employee("Michel")
+            .unwrap();
+
+        // Assert
+        assert_eq!(result.minutes_per_action(), actions);
+    }
+
+    #[test]
+    fn it_should_resolve_names_by_id_and_keep_namesakes_apart() {
+        // Arrange
+        let event = |unique_id: i32, employee_id: u32, action_id: u32, time: &str| {
+            punch("default", unique_id, employee_id, action_id, &format!("2019-01-02T{}", time))
+        };
+        let events = vec![
+            event(1, 7, 1, "07:00:00"),
+            event(2, 7, 2, "08:00:00"),
+            event(3, 8, 1, "07:00:00"),
+            event(4, 8, 2, "07:30:00"),
+        ];
+        let mut master_data = MasterDataPerSource::default();
+        let terminal = master_data.source_mut("default");
+        terminal.employees.insert(7, "Jan".to_string());
+        terminal.employees.insert(8, "Jan".to_string());
+        terminal.actions.insert(1, "Begin/Pauze".to_string());
+        terminal.actions.insert(2, "Kas".to_string());
+
+        // Act
+        let work_sheet = crate::derive_work_sheet(events, &master_data, None, &Settings::default());
+        let day = work_sheet.day(&NaiveDate::from_ymd(2019, 1, 2)).unwrap();
+
+        // Assert
+        assert_eq!(day.employee("Jan (7)").unwrap().minutes("Kas"), Some(60));
+        assert_eq!(day.employee("Jan (8)").unwrap().minutes("Kas"), Some(30));
+        assert_eq!(day.employee("Jan (8)").unwrap().employee_id, Some(8));
+    }
+
+    #[test]
+    fn it_should_merge_punches_from_several_terminals() {
+        // Arrange
+        let events = vec![
+            punch("kas-noord", 1, 7, 1, "2019-01-02T07:00:00"),
+            punch("kas-zuid", 1, 7, 2, "2019-01-02T09:00:00"),
+            punch("kas-noord", 2, 7, 3, "2019-01-02T10:00:00"),
+        ];
+
+        // Act
+        let work_sheet = crate::derive_work_sheet(events, &master_data(), None, &Settings::default());
         let result = work_sheet
-            .get(&NaiveDate::from_ymd(2019, 1, 2))
+            .day(&NaiveDate::from_ymd(2019, 1, 2))
             .unwrap()
-            .get("Michel")
+            .employee("Jan")
             .unwrap();
 
         // Assert
-        assert_eq!(result, &actions);
+        assert_eq!(result.minutes("Kas"), Some(120));
+        assert_eq!(result.minutes("toppen B"), Some(60));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn it_should_derive_the_work_sheet_as_it_was_known_at_a_moment() {
+        // Arrange
+        let imported_at = |moment: &str| NaiveDateTime::parse_from_str(moment, "%Y-%m-%dT%H:%M:%S").unwrap();
+        let imported = |unique_id: i32, action_id: u32, timestamp: &str, revision: i32, moment: &str| Event {
+            revision,
+            timestamp: imported_at(moment),
+            ..punch("default", unique_id, 7, action_id, timestamp)
+        };
+        let events = vec![
+            imported(1, 1, "2019-01-02T07:00:00", 0, "2019-01-11T15:33:43"),
+            imported(2, 2, "2019-01-02T08:00:00", 0, "2019-01-11T15:33:43"),
+            imported(3, 1, "2019-01-02T09:00:00", 0, "2019-01-11T15:33:43"),
+            imported(2, 3, "2019-01-02T08:00:00", 1, "2019-01-20T12:00:00"),
+        ];
+
+        // Act
+        let before = crate::derive_work_sheet(events.clone(), &master_data(), Some(imported_at("2019-01-12T00:00:00")), &Settings::default());
+        let after = crate::derive_work_sheet(events, &master_data(), None, &Settings::default());
+
+        // Assert
+        let day = NaiveDate::from_ymd(2019, 1, 2);
+        assert_eq!(minutes(&before, day, "Jan", "Kas"), Some(60));
+        assert_eq!(minutes(&after, day, "Jan", "Kas"), None);
+        assert_eq!(minutes(&after, day, "Jan", "toppen B"), Some(60));
+    }
+
+    #[test]
+    fn it_should_count_a_forgotten_clock_in_once_it_is_corrected() {
+        // Arrange
+        let mut clock_in = punch("default", 10, 7, 1, "2019-01-02T07:00:00");
+        clock_in.event_type = events::PUNCH_CORRECTED.to_string();
+        clock_in.payload = "{\"author\":\"Michel\",\"reason\":\"Vergeten in te klokken\",\"correction\":{\"kind\":\"add\",\"employee_id\":7,\"action_id\":1,\"timestamp\":\"2019-01-02T07:00:00\"}}".to_string();
+        let punches = vec![
+            punch("default", 1, 7, 2, "2019-01-02T08:00:00"),
+            punch("default", 2, 7, 3, "2019-01-02T09:00:00"),
+        ];
+
+        // Act
+        let uncorrected = crate::derive_work_sheet(punches.clone(), &master_data(), None, &Settings::default());
+        let mut events = punches;
+        events.push(clock_in);
+        let corrected = crate::derive_work_sheet(events, &master_data(), None, &Settings::default());
+
+        // Assert
+        let day = NaiveDate::from_ymd(2019, 1, 2);
+        assert_eq!(minutes(&uncorrected, day, "Jan", "Kas"), None);
+        assert_eq!(minutes(&corrected, day, "Jan", "Kas"), Some(60));
+        assert_eq!(minutes(&corrected, day, "Jan", "toppen B"), Some(60));
+    }
+
+    #[test]
+    fn it_should_report_anomalies_per_employee_per_day() {
+        // Arrange
+        let at = |moment: &str| NaiveDateTime::parse_from_str(moment, "%Y-%m-%dT%H:%M:%S").unwrap();
+        let events = vec![
+            punch("default", 1, 7, 2, "2019-01-02T07:00:00"),
+            punch("default", 2, 7, 3, "2019-01-02T08:00:00"),
+            punch("default", 3, 7, 3, "2019-01-02T08:00:30"),
+            punch("default", 4, 7, 1, "2019-01-02T09:00:00"),
+            punch("default", 5, 8, 1, "2019-01-03T07:00:00"),
+        ];
+
+        // Act
+        let anomalies = crate::derive_work_sheet(events, &master_data(), None, &Settings::default()).anomalies();
+
+        // Assert
+        assert_eq!(anomalies[&NaiveDate::from_ymd(2019, 1, 2)]["Jan"], vec![
+            crate::Anomaly::MissingClockIn { at: at("2019-01-02T07:00:00"), action: "Kas".to_string() },
+            crate::Anomaly::DuplicatePunch { at: at("2019-01-02T08:00:30"), seconds_apart: 30 },
+            crate::Anomaly::OpenBreak { since: at("2019-01-02T09:00:00") },
+        ]);
+        assert_eq!(anomalies[&NaiveDate::from_ymd(2019, 1, 3)]["Piet"], vec![
+            crate::Anomaly::SinglePunch { at: at("2019-01-03T07:00:00") },
+        ]);
+    }
+
+    #[test]
+    fn it_should_report_a_negative_duration_after_the_break_policy() {
+        // Arrange
+        let at = |moment: &str| NaiveDateTime::parse_from_str(moment, "%Y-%m-%dT%H:%M:%S").unwrap();
+        let day = NaiveDate::from_ymd(2019, 1, 2);
+        let segments = vec![
+            Segment::new(SegmentKind::Work, Some("Kas".to_string()), at("2019-01-02T07:00:00"), at("2019-01-02T08:00:00")),
+            Segment::new(SegmentKind::Work, Some("Kas".to_string()), at("2019-01-02T08:30:00"), at("2019-01-02T08:00:00")),
+        ];
+        let mut anomalies = HashMap::new();
+
+        // Act
+        crate::check_durations(&mut anomalies, day, "Jan", &segments);
+
+        // Assert
+        assert_eq!(anomalies[&day]["Jan"], vec![
+            crate::Anomaly::NegativeDuration { at: at("2019-01-02T08:30:00"), action: "Kas".to_string(), minutes: -30 },
+        ]);
+    }
+
+    #[test]
+    fn it_should_fold_the_totals_over_the_timeline() {
+        // Arrange
+        let events = vec![
+            punch("default", 1, 7, 1, "2019-01-02T07:00:00"),
+            punch("default", 2, 7, 1, "2019-01-02T10:00:00"),
+            punch("default", 3, 7, 1, "2019-01-02T10:15:00"),
+            punch("default", 4, 7, 2, "2019-01-02T12:00:00"),
+            punch("default", 5, 7, 1, "2019-01-02T12:30:00"),
+        ];
+
+        // Act
+        let work_sheet = crate::derive_work_sheet(events, &master_data(), None, &Settings::default());
+        let jan = work_sheet.day(&NaiveDate::from_ymd(2019, 1, 2)).unwrap().employee("Jan").unwrap();
+
+        // Assert
+        let kinds: Vec<SegmentKind> = jan.segments.iter().map(|segment| segment.kind).collect();
+        assert_eq!(kinds, vec![SegmentKind::Work, SegmentKind::Break, SegmentKind::Work, SegmentKind::Unassigned]);
+        assert_eq!(jan.segments[2].action, Some("Kas".to_string()));
+        assert_eq!(jan.segments[3].seconds, 30 * 60);
+        assert_eq!(jan.minutes("Kas"), Some(285));
+    }
+
+    #[test]
+    fn it_should_apply_the_break_policy() {
+        // Arrange
+        let at = |moment: &str| NaiveDateTime::parse_from_str(moment, "%Y-%m-%dT%H:%M:%S").unwrap();
+        let events = vec![
+            punch("default", 1, 7, 1, "2019-01-02T07:00:00"),
+            punch("default", 2, 7, 1, "2019-01-02T10:00:00"),
+            punch("default", 3, 7, 1, "2019-01-02T10:10:00"),
+            punch("default", 4, 7, 2, "2019-01-02T14:00:00"),
+            punch("default", 5, 8, 1, "2019-01-02T07:00:00"),
+            punch("default", 6, 8, 1, "2019-01-02T08:00:00"),
+            punch("default", 7, 8, 1, "2019-01-02T10:00:00"),
+            punch("default", 8, 8, 3, "2019-01-02T11:00:00"),
+        ];
+        let settings = Settings {
+            breaks: BreakPolicy {
+                paid_below_minutes: Some(15),
+                minimum: Some(MinimumBreak { after_minutes: 330, minutes: 30 }),
+                maximum_minutes: Some(60),
+                line_item: true,
+            },
+            ..Settings::default()
+        };
+
+        // Act
+        let work_sheet = crate::derive_work_sheet(events, &master_data(), None, &settings);
+        let day = work_sheet.day(&NaiveDate::from_ymd(2019, 1, 2)).unwrap();
+
+        // Assert
+        let jan = day.employee("Jan").unwrap();
+        assert_eq!(jan.minutes("Kas"), Some(390));
+        assert_eq!(jan.breaks, Some(BreakTotal { count: 1, seconds: 30 * 60, minutes: 30 }));
+        assert_eq!(jan.totals, DayTotals { work: 390, breaks: 30, presence: 420 });
+        assert_eq!(jan.break_rules, vec![
+            AppliedBreakRule::Paid { at: at("2019-01-02T10:00:00"), seconds: 10 * 60 },
+            AppliedBreakRule::Deducted { at: at("2019-01-02T12:30:00"), seconds: 30 * 60 },
+        ]);
+        let piet = day.employee("Piet").unwrap();
+        assert_eq!(piet.minutes("toppen B"), Some(120));
+        assert_eq!(piet.break_rules, vec![AppliedBreakRule::Capped { at: at("2019-01-02T09:00:00"), seconds: 60 * 60 }]);
+    }
+
+    #[test]
+    fn it_should_keep_the_break_line_item_apart_from_an_action_of_the_same_name() {
+        // Arrange
+        let events = vec![
+            punch("default", 1, 7, 1, "2019-01-02T07:00:00"),
+            punch("default", 2, 7, 4, "2019-01-02T08:00:00"),
+            punch("default", 3, 7, 1, "2019-01-02T08:00:00"),
+            punch("default", 4, 7, 1, "2019-01-02T08:30:00"),
+            punch("default", 5, 7, 2, "2019-01-02T09:00:00"),
+        ];
+        let mut master_data = master_data();
+        master_data.source_mut("default").actions.insert(4, "Pauze".to_string());
+        let mut settings = Settings::default();
+        settings.breaks.line_item = true;
+
+        // Act
+        let work_sheet = crate::derive_work_sheet(events, &master_data, None, &settings);
+
+        // Assert
+        let jan = work_sheet.day(&NaiveDate::from_ymd(2019, 1, 2)).and_then(|day| day.employee("Jan")).unwrap();
+        assert_eq!(jan.minutes_per_action().get("Pauze"), Some(&60));
+        assert_eq!(jan.breaks, Some(BreakTotal { count: 1, seconds: 30 * 60, minutes: 30 }));
+    }
+
+    #[test]
+    fn it_should_report_a_night_shift_on_the_day_it_started() {
+        // Arrange
+        let events = vec![
+            punch("default", 1, 7, 1, "2019-01-02T22:00:00"),
+            punch("default", 2, 7, 2, "2019-01-03T02:00:00"),
+            punch("default", 3, 7, 1, "2019-01-03T02:30:00"),
+            punch("default", 4, 7, 1, "2019-01-03T03:00:00"),
+            punch("default", 5, 7, 3, "2019-01-03T06:00:00"),
+        ];
+        let by_continuity = Settings { shifts: ShiftPolicy::Continuity { max_gap_minutes: 240 }, ..Settings::default() };
+        let by_boundary = Settings {
+            shifts: ShiftPolicy::DayBoundary { day_start: NaiveTime::from_hms(12, 0, 0) },
+            ..Settings::default()
+        };
+
+        // Act
+        let midnight = crate::derive_work_sheet(events.clone(), &master_data(), None, &Settings::default());
+        let continuity = crate::derive_work_sheet(events.clone(), &master_data(), None, &by_continuity);
+        let boundary = crate::derive_work_sheet(events, &master_data(), None, &by_boundary);
+
+        // Assert
+        let day = NaiveDate::from_ymd(2019, 1, 2);
+        assert_eq!(minutes(&midnight, day, "Jan", "Kas"), None);
+        for work_sheet in &[continuity, boundary] {
+            assert_eq!(work_sheet.dates(), vec![day]);
+            assert_eq!(minutes(work_sheet, day, "Jan", "Kas"), Some(240));
+            assert_eq!(minutes(work_sheet, day, "Jan", "toppen B"), Some(210));
+        }
+    }
+
+    #[test]
+    fn it_should_derive_the_work_days_of_touched_punches_like_the_work_sheet() {
+        // Arrange
+        let events = vec![
+            punch("default", 1, 7, 1, "2019-01-02T22:00:00"),
+            punch("default", 2, 7, 2, "2019-01-03T02:00:00"),
+        ];
+        let time_row = |event: &Event| serde_json::from_str::<TimeRowEvent>(&event.payload).unwrap();
+        let reverted = punch("default", 3, 7, 3, "2019-01-03T03:00:00");
+        let punches = vec![
+            ("default".to_string(), time_row(&events[1])),
+            ("default".to_string(), time_row(&reverted)),
+        ];
+        let settings = Settings { shifts: ShiftPolicy::Continuity { max_gap_minutes: 240 }, ..Settings::default() };
+
+        // Act
+        let days = crate::derive_days(events, &master_data(), &punches, &settings);
+
+        // Assert
+        let day = NaiveDate::from_ymd(2019, 1, 2);
+        assert_eq!(days.keys().cloned().collect::<Vec<_>>(), vec![day]);
+        assert_eq!(days[&day].employee("Jan").and_then(|jan| jan.minutes("Kas")), Some(240));
+    }
+
+    #[test]
+    fn it_should_start_the_work_day_when_the_site_does() {
+        // Arrange
+        let events = vec![
+            punch("kas-noord", 1, 7, 1, "2019-01-03T02:00:00"),
+            punch("kas-noord", 2, 7, 2, "2019-01-03T03:30:00"),
+            punch("default", 1, 8, 1, "2019-01-03T02:00:00"),
+            punch("default", 2, 8, 2, "2019-01-03T03:30:00"),
+        ];
+        let settings = Settings {
+            sites: vec![Site {
+                name: "Noord".to_string(),
+                terminals: vec!["kas-noord".to_string()],
+                day_start: NaiveTime::from_hms(4, 0, 0),
+            }],
+            ..Settings::default()
+        };
+
+        // Act
+        let work_sheet = crate::derive_work_sheet(events, &master_data(), None, &settings);
+
+        // Assert
+        assert_eq!(minutes(&work_sheet, NaiveDate::from_ymd(2019, 1, 2), "Jan", "Kas"), Some(90));
+        assert_eq!(minutes(&work_sheet, NaiveDate::from_ymd(2019, 1, 3), "Piet", "Kas"), Some(90));
+    }
+
+    #[test]
+    fn it_should_derive_with_the_configured_action_roles() {
+        // Arrange
+        let events = vec![
+            punch("default", 1, 7, 11, "2019-01-02T07:00:00"),
+            punch("default", 2, 7, 2, "2019-01-02T09:00:00"),
+            punch("default", 3, 7, 12, "2019-01-02T10:00:00"),
+            punch("default", 4, 7, 12, "2019-01-02T10:30:00"),
+            punch("default", 5, 7, 2, "2019-01-02T12:00:00"),
+            punch("default", 6, 7, 13, "2019-01-02T12:30:00"),
+        ];
+        let mut master_data = master_data();
+        let terminal = master_data.source_mut("default");
+        terminal.actions.insert(11, "Clock in".to_string());
+        terminal.actions.insert(12, "Break".to_string());
+        terminal.actions.insert(13, "Clock out".to_string());
+        let settings = Settings {
+            roles: vec![
+                ActionRole { action_id: Some(11), action: None, role: Role::StartOfDay },
+                ActionRole { action_id: Some(12), action: None, role: Role::BreakToggle },
+                ActionRole { action_id: None, action: Some("Clock out".to_string()), role: Role::EndOfDay },
+            ],
+            ..Settings::default()
+        };
+
+        // Act
+        let work_sheet = crate::derive_work_sheet(events, &master_data, None, &settings);
+        let jan = work_sheet.day(&NaiveDate::from_ymd(2019, 1, 2)).unwrap().employee("Jan").unwrap();
+
+        // Assert
+        assert_eq!(jan.minutes("Kas"), Some(270));
+        assert_eq!(jan.segments.last().map(|segment| segment.kind), Some(SegmentKind::Unassigned));
+        assert!(jan.anomalies.is_empty());
+    }
+
+    #[test]
+    fn it_should_credit_the_action_that_was_started_on_terminals_that_punch_it_at_the_start() {
+        // Arrange
+        let events = vec![
+            punch("kas-nieuw", 1, 7, 1, "2019-01-02T07:00:00"),
+            punch("kas-nieuw", 2, 7, 2, "2019-01-02T07:05:00"),
+            punch("kas-nieuw", 3, 7, 1, "2019-01-02T10:00:00"),
+            punch("kas-nieuw", 4, 7, 1, "2019-01-02T10:30:00"),
+            punch("kas-nieuw", 5, 7, 3, "2019-01-02T11:00:00"),
+            punch("kas-nieuw", 6, 7, 1, "2019-01-02T12:00:00"),
+        ];
+        let mut settings = Settings::default();
+        settings.punch_semantics.insert("kas-nieuw".to_string(), PunchSemantics::ActionAtStart);
+
+        // Act
+        let work_sheet = crate::derive_work_sheet(events, &master_data(), None, &settings);
+        let jan = work_sheet.day(&NaiveDate::from_ymd(2019, 1, 2)).unwrap().employee("Jan").unwrap();
+
+        // Assert
+        assert_eq!(jan.minutes("Kas"), Some(205));
+        assert_eq!(jan.minutes("toppen B"), Some(60));
+        assert_eq!(jan.segments[0].kind, SegmentKind::Unassigned);
+    }
+
+    #[test]
+    fn it_should_add_up_the_days_of_a_period() {
+        // Arrange
+        let events = vec![
+            punch("default", 1, 7, 1, "2019-01-07T07:00:00"),
+            punch("default", 2, 7, 2, "2019-01-07T09:00:00"),
+            punch("default", 3, 7, 1, "2019-01-08T07:00:00"),
+            punch("default", 4, 7, 2, "2019-01-08T08:30:00"),
+            punch("default", 5, 7, 1, "2019-01-14T07:00:00"),
+            punch("default", 6, 7, 2, "2019-01-14T08:00:00"),
+        ];
+        let settings = Settings::default();
+        let week = Period::parse(PeriodKind::IsoWeek, "2019-W02", &settings.periods).unwrap();
+        let month = Period::parse(PeriodKind::Month, "2019-01", &settings.periods).unwrap();
+
+        // Act
+        let work_sheet = crate::derive_work_sheet(events, &master_data(), None, &settings);
+
+        // Assert
+        let jan = &work_sheet.period(&week).employees["Jan"];
+        assert_eq!(jan.days, 2);
+        assert_eq!(jan.actions["Kas"].minutes, 210);
+        assert_eq!(jan.totals.work, 210);
+        assert_eq!(work_sheet.period(&month).employees["Jan"].actions["Kas"].minutes, 270);
+    }
+}
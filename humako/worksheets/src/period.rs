@@ -0,0 +1,173 @@
+use std::collections::BTreeMap;
+
+use chrono::{Duration, NaiveDate, Weekday};
+
+use crate::work_sheet::{ActionTotal, DayTotals, EmployeeDay, WorkSheet};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PeriodKind {
+    // 2019-W02
+    IsoWeek,
+    // 2019-01
+    Month,
+    // 2019-P03, 13 periods of four weeks from the Monday of `first_week`.
+    // The last one runs up to period 1 of the next year.
+    FourWeekly,
+}
+
+// The 4-weekly payroll periods start on the Monday of this ISO week.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PeriodSettings {
+    pub first_week: u32,
+}
+
+impl Default for PeriodSettings {
+    fn default() -> Self {
+        PeriodSettings { first_week: 1 }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Period {
+    pub kind: PeriodKind,
+    pub id: String,
+    pub first_day: NaiveDate,
+    pub last_day: NaiveDate,
+}
+
+impl Period {
+    pub fn parse(kind: PeriodKind, id: &str, settings: &PeriodSettings) -> Option<Period> {
+        let (first_day, last_day) = match kind {
+            PeriodKind::IsoWeek => {
+                let (year, week) = split(id, "-W")?;
+                let first_day = NaiveDate::from_isoywd_opt(year, week, Weekday::Mon)?;
+
+                (first_day, first_day + Duration::days(6))
+            }
+            PeriodKind::Month => {
+                let (year, month) = split(id, "-")?;
+                let first_day = NaiveDate::from_ymd_opt(year, month, 1)?;
+                let next = match month {
+                    12 => NaiveDate::from_ymd_opt(year + 1, 1, 1)?,
+                    _ => NaiveDate::from_ymd_opt(year, month + 1, 1)?,
+                };
+
+                (first_day, next.pred())
+            }
+            PeriodKind::FourWeekly => {
+                let (year, period) = split(id, "-P")?;
+                if !(1..=13).contains(&period) {
+                    return None;
+                }
+                let start = NaiveDate::from_isoywd_opt(year, settings.first_week, Weekday::Mon)?;
+                let first_day = start + Duration::weeks(4 * (period as i64 - 1));
+                let last_day = match period {
+                    13 => NaiveDate::from_isoywd_opt(year + 1, settings.first_week, Weekday::Mon)?.pred(),
+                    _ => first_day + Duration::days(27),
+                };
+
+                (first_day, last_day)
+            }
+        };
+
+        Some(Period { kind, id: id.to_string(), first_day, last_day })
+    }
+
+    pub fn contains(&self, date: &NaiveDate) -> bool {
+        self.first_day <= *date && *date <= self.last_day
+    }
+}
+
+fn split(id: &str, separator: &str) -> Option<(i32, u32)> {
+    let mut parts = id.splitn(2, separator);
+    let year = parts.next()?.parse().ok()?;
+    let number = parts.next()?.parse().ok()?;
+
+    Some((year, number))
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PeriodTotals {
+    pub period: Period,
+    pub employees: BTreeMap<String, EmployeeTotals>,
+}
+
+// The employee-days of a period added up, the minutes are the rounded minutes
+// of every day.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EmployeeTotals {
+    pub employee_id: Option<u32>,
+    pub name: String,
+    pub days: usize,
+    pub actions: BTreeMap<String, ActionTotal>,
+    pub totals: DayTotals,
+}
+
+impl EmployeeTotals {
+    fn add(&mut self, day: &EmployeeDay) {
+        self.days += 1;
+
+        day.actions.values().for_each(|day_total| {
+            let total = self.actions
+                .entry(day_total.action.clone())
+                .or_insert_with(|| ActionTotal { action: day_total.action.clone(), seconds: 0, minutes: 0 });
+            total.seconds += day_total.seconds;
+            total.minutes += day_total.minutes;
+        });
+
+        self.totals.work += day.totals.work;
+        self.totals.breaks += day.totals.breaks;
+        self.totals.presence += day.totals.presence;
+    }
+}
+
+impl WorkSheet {
+    pub fn period(&self, period: &Period) -> PeriodTotals {
+        let mut employees: BTreeMap<String, EmployeeTotals> = BTreeMap::new();
+
+        self.days
+            .values()
+            .filter(|day| period.contains(&day.date))
+            .flat_map(|day| day.employees.iter())
+            .for_each(|(label, day)| {
+                employees
+                    .entry(label.clone())
+                    .or_insert_with(|| EmployeeTotals {
+                        employee_id: day.employee_id,
+                        name: day.name.clone(),
+                        days: 0,
+                        actions: BTreeMap::new(),
+                        totals: DayTotals::default(),
+                    })
+                    .add(day);
+            });
+
+        PeriodTotals { period: period.clone(), employees }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    #[test]
+    fn it_should_parse_periods() {
+        let settings = PeriodSettings::default();
+        let days = |kind, id| Period::parse(kind, id, &settings).map(|period| (period.first_day, period.last_day));
+
+        assert_eq!(days(PeriodKind::IsoWeek, "2019-W01"), Some((NaiveDate::from_ymd(2018, 12, 31), NaiveDate::from_ymd(2019, 1, 6))));
+        assert_eq!(days(PeriodKind::Month, "2019-02"), Some((NaiveDate::from_ymd(2019, 2, 1), NaiveDate::from_ymd(2019, 2, 28))));
+        assert_eq!(days(PeriodKind::FourWeekly, "2019-P02"), Some((NaiveDate::from_ymd(2019, 1, 28), NaiveDate::from_ymd(2019, 2, 24))));
+        // 2020 has 53 ISO weeks, the last period gets five
+        assert_eq!(days(PeriodKind::FourWeekly, "2020-P13"), Some((NaiveDate::from_ymd(2020, 11, 30), NaiveDate::from_ymd(2021, 1, 3))));
+        assert_eq!(days(PeriodKind::FourWeekly, "2019-P14"), None);
+        assert_eq!(days(PeriodKind::Month, "2019-13"), None);
+        // The last month chrono can represent has no next month to end it
+        assert_eq!(days(PeriodKind::Month, "262143-12"), None);
+    }
+}
@@ -0,0 +1,54 @@
+use db_parser::TimeRowEvent;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    StartOfDay,
+    // Clocks in at the start of the day, after that starts and ends breaks
+    BreakToggle,
+    EndOfDay,
+    // Depending on the terminal the time before or after the punch was
+    // spent on this action
+    Work,
+}
+
+impl Role {
+    pub fn clocks_in(self) -> bool {
+        self == Role::StartOfDay || self == Role::BreakToggle
+    }
+}
+
+// Whether a terminal records the action when the employee finishes it, or
+// when they start it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PunchSemantics {
+    #[default]
+    ActionAtEnd,
+    ActionAtStart,
+}
+
+// Matched on the action id of the terminal, or on the action name when either
+// is missing one, like punches imported before ids were kept.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ActionRole {
+    #[serde(default)]
+    pub action_id: Option<u32>,
+    #[serde(default)]
+    pub action: Option<String>,
+    pub role: Role,
+}
+
+impl ActionRole {
+    pub fn matches(&self, time_row: &TimeRowEvent) -> bool {
+        match (self.action_id, time_row.action_id) {
+            (Some(a), Some(b)) => a == b,
+            _ => self.action.as_ref() == Some(&time_row.action),
+        }
+    }
+}
+
+// What the terminals were set up with so far.
+pub fn default_roles() -> Vec<ActionRole> {
+    vec![ActionRole { action_id: None, action: Some("Begin/Pauze".to_string()), role: Role::BreakToggle }]
+}
@@ -0,0 +1,82 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Rounding {
+    #[serde(rename = "truncate")]
+    Truncate,
+    #[serde(rename = "nearest")]
+    Nearest,
+    #[serde(rename = "up_to_5")]
+    UpTo5,
+    #[serde(rename = "up_to_15")]
+    UpTo15,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RoundingScope {
+    // Every punched segment is rounded before the segments are added up
+    PerPunch,
+    // The seconds of an action on a day are added up, then rounded once
+    PerDay,
+}
+
+// Durations are kept in seconds, they only become minutes on the work sheet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RoundingPolicy {
+    pub rounding: Rounding,
+    pub scope: RoundingScope,
+}
+
+impl Default for RoundingPolicy {
+    fn default() -> Self {
+        RoundingPolicy { rounding: Rounding::Truncate, scope: RoundingScope::PerDay }
+    }
+}
+
+impl Rounding {
+    pub fn minutes(self, seconds: i64) -> i32 {
+        let minutes = match self {
+            Rounding::Truncate => seconds / 60,
+            Rounding::Nearest => (seconds + 30) / 60,
+            Rounding::UpTo5 => up_to(seconds, 5),
+            Rounding::UpTo15 => up_to(seconds, 15),
+        };
+
+        minutes as i32
+    }
+}
+
+impl RoundingPolicy {
+    pub fn minutes<I: IntoIterator<Item = i64>>(&self, segments: I) -> i32 {
+        match self.scope {
+            RoundingScope::PerPunch => segments.into_iter().map(|seconds| self.rounding.minutes(seconds)).sum(),
+            RoundingScope::PerDay => self.rounding.minutes(segments.into_iter().sum()),
+        }
+    }
+}
+
+fn up_to(seconds: i64, step: i64) -> i64 {
+    let step = step * 60;
+
+    (seconds + step - 1) / step * step / 60
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_round_at_the_configured_moment() {
+        let segments = vec![7 * 60 + 50, 7 * 60 + 50, 60];
+
+        let per_day = RoundingPolicy { rounding: Rounding::Truncate, scope: RoundingScope::PerDay };
+        let per_punch = RoundingPolicy { rounding: Rounding::Truncate, scope: RoundingScope::PerPunch };
+        let up_to_15 = RoundingPolicy { rounding: Rounding::UpTo15, scope: RoundingScope::PerDay };
+
+        assert_eq!(per_day.minutes(segments.clone()), 16);
+        assert_eq!(per_punch.minutes(segments.clone()), 15);
+        assert_eq!(up_to_15.minutes(segments), 30);
+        assert_eq!(Rounding::Nearest.minutes(89), 1);
+        assert_eq!(Rounding::UpTo5.minutes(300), 5);
+    }
+}
@@ -0,0 +1,105 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io;
+
+use chrono::NaiveTime;
+use db_parser::TimeRowEvent;
+
+use crate::breaks::BreakPolicy;
+use crate::period::PeriodSettings;
+use crate::roles::{default_roles, ActionRole, PunchSemantics, Role};
+use crate::rounding::RoundingPolicy;
+use crate::shifts::ShiftPolicy;
+
+// How punches become a work sheet. Read from the JSON file HUMAKO_SETTINGS
+// points to, every setting left out keeps its default.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub rounding: RoundingPolicy,
+    pub breaks: BreakPolicy,
+    pub shifts: ShiftPolicy,
+    pub sites: Vec<Site>,
+    // Actions without a role are work
+    pub roles: Vec<ActionRole>,
+    // Per terminal, the terminals left out record the action at the end
+    pub punch_semantics: BTreeMap<String, PunchSemantics>,
+    pub periods: PeriodSettings,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            rounding: RoundingPolicy::default(),
+            breaks: BreakPolicy::default(),
+            shifts: ShiftPolicy::default(),
+            sites: vec![],
+            roles: default_roles(),
+            punch_semantics: BTreeMap::new(),
+            periods: PeriodSettings::default(),
+        }
+    }
+}
+
+// A location with its own terminals, where the work day may start at another
+// time than midnight.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Site {
+    pub name: String,
+    pub terminals: Vec<String>,
+    pub day_start: NaiveTime,
+}
+
+#[derive(Debug)]
+pub enum SettingsError {
+    Open { path: String, error: io::Error },
+    Parse { path: String, error: serde_json::Error },
+}
+
+impl fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SettingsError::Open { path, error } => write!(f, "Error opening settings {}: {}", path, error),
+            SettingsError::Parse { path, error } => write!(f, "Error parsing settings {}: {}", path, error),
+        }
+    }
+}
+
+impl Error for SettingsError {}
+
+impl Settings {
+    // Meant to be loaded once at startup, a broken file stops the program
+    // before anything is derived with the wrong settings.
+    pub fn load() -> Result<Settings, SettingsError> {
+        match env::var("HUMAKO_SETTINGS") {
+            Ok(path) => {
+                let file = File::open(&path).map_err(|error| SettingsError::Open { path: path.clone(), error })?;
+
+                serde_json::from_reader(file).map_err(|error| SettingsError::Parse { path, error })
+            }
+            Err(_) => Ok(Settings::default()),
+        }
+    }
+
+    pub fn role(&self, time_row: &TimeRowEvent) -> Role {
+        self.roles
+            .iter()
+            .find(|role| role.matches(time_row))
+            .map(|role| role.role)
+            .unwrap_or(Role::Work)
+    }
+
+    pub fn semantics(&self, terminal: &str) -> PunchSemantics {
+        self.punch_semantics.get(terminal).cloned().unwrap_or_default()
+    }
+
+    pub fn day_start(&self, terminal: &str) -> Option<NaiveTime> {
+        self.sites
+            .iter()
+            .find(|site| site.terminals.iter().any(|site_terminal| site_terminal == terminal))
+            .map(|site| site.day_start)
+    }
+}
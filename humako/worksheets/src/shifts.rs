@@ -0,0 +1,55 @@
+use chrono::{Duration, NaiveDate, NaiveDateTime, NaiveTime};
+
+// Which work day a punch belongs to. A shift is reported on the date it
+// started, even when it runs past midnight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ShiftPolicy {
+    // Punches before `day_start`, or the day start of their site, belong to
+    // the day before
+    DayBoundary { day_start: NaiveTime },
+    // A punch at most `max_gap_minutes` after the previous one continues its
+    // shift, a longer gap starts a new one on the work day of its site
+    Continuity { max_gap_minutes: i64 },
+}
+
+impl Default for ShiftPolicy {
+    fn default() -> Self {
+        ShiftPolicy::DayBoundary { day_start: NaiveTime::from_hms(0, 0, 0) }
+    }
+}
+
+impl ShiftPolicy {
+    // The punches of one employee in order, with the day start of the site
+    // they were punched at.
+    pub fn work_days(&self, punches: &[(NaiveDateTime, Option<NaiveTime>)]) -> Vec<NaiveDate> {
+        let midnight = NaiveTime::from_hms(0, 0, 0);
+
+        match *self {
+            ShiftPolicy::DayBoundary { day_start } => punches
+                .iter()
+                .map(|(timestamp, site_start)| work_day(*timestamp, site_start.unwrap_or(day_start)))
+                .collect(),
+            ShiftPolicy::Continuity { max_gap_minutes } => {
+                let mut previous: Option<(NaiveDateTime, NaiveDate)> = None;
+
+                punches
+                    .iter()
+                    .map(|(timestamp, site_start)| {
+                        let day = match previous {
+                            Some((at, day)) if *timestamp - at <= Duration::minutes(max_gap_minutes) => day,
+                            _ => work_day(*timestamp, site_start.unwrap_or(midnight)),
+                        };
+                        previous = Some((*timestamp, day));
+
+                        day
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+pub fn work_day(timestamp: NaiveDateTime, day_start: NaiveTime) -> NaiveDate {
+    (timestamp - day_start.signed_duration_since(NaiveTime::from_hms(0, 0, 0))).date()
+}
@@ -0,0 +1,235 @@
+use std::collections::BTreeMap;
+
+use chrono::{NaiveDate, NaiveDateTime};
+
+use crate::breaks::AppliedBreakRule;
+use crate::settings::Settings;
+use crate::Anomaly;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct WorkSheet {
+    pub days: BTreeMap<NaiveDate, WorkDay>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WorkDay {
+    pub date: NaiveDate,
+    // Keyed on the label shown on the work sheet, a name shared by several
+    // employees gets their id appended
+    pub employees: BTreeMap<String, EmployeeDay>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EmployeeDay {
+    pub employee_id: Option<u32>,
+    pub name: String,
+    // Only ever added to through `add_segment`, so it is the sum of the
+    // work segments. The minutes are what `summarize` made of the seconds.
+    pub actions: BTreeMap<String, ActionTotal>,
+    // Only with `line_item` in the break policy
+    pub breaks: Option<BreakTotal>,
+    pub totals: DayTotals,
+    pub segments: Vec<Segment>,
+    pub break_rules: Vec<AppliedBreakRule>,
+    pub anomalies: Vec<Anomaly>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ActionTotal {
+    pub action: String,
+    pub seconds: i64,
+    pub minutes: i32,
+}
+
+// The unpaid breaks, paid breaks count as work.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BreakTotal {
+    pub count: usize,
+    pub seconds: i64,
+    pub minutes: i32,
+}
+
+// Presence runs from the first punch to the last, so it holds the time that
+// could not be assigned to an action as well.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DayTotals {
+    pub work: i32,
+    pub breaks: i32,
+    pub presence: i32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SegmentKind {
+    Work,
+    Break,
+    // Clocked in, but no punch ever said what on
+    Unassigned,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Segment {
+    pub start: NaiveDateTime,
+    pub end: NaiveDateTime,
+    pub kind: SegmentKind,
+    pub action: Option<String>,
+    pub seconds: i64,
+    pub paid: bool,
+}
+
+impl Segment {
+    pub fn new(kind: SegmentKind, action: Option<String>, start: NaiveDateTime, end: NaiveDateTime) -> Segment {
+        let seconds = (end - start).num_seconds();
+
+        Segment { start, end, kind, action, seconds, paid: false }
+    }
+
+    // The action the segment counts for, a paid break counts for the action
+    // it interrupted.
+    pub fn credited_action(&self) -> Option<&String> {
+        match self.kind {
+            SegmentKind::Work => self.action.as_ref(),
+            SegmentKind::Break if self.paid => self.action.as_ref(),
+            _ => None,
+        }
+    }
+}
+
+impl WorkSheet {
+    pub fn day(&self, date: &NaiveDate) -> Option<&WorkDay> {
+        self.days.get(date)
+    }
+
+    pub fn dates(&self) -> Vec<NaiveDate> {
+        self.days.keys().cloned().collect()
+    }
+
+    pub fn total_minutes(&self) -> i32 {
+        self.days.values().map(WorkDay::total_minutes).sum()
+    }
+
+    pub fn summarize(&mut self, settings: &Settings) {
+        self.days
+            .values_mut()
+            .flat_map(|day| day.employees.values_mut())
+            .for_each(|employee| employee.summarize(settings));
+    }
+
+    // Only the days and employees that have anomalies.
+    pub fn anomalies(&self) -> BTreeMap<NaiveDate, BTreeMap<String, Vec<Anomaly>>> {
+        self.days
+            .iter()
+            .map(|(date, day)| {
+                let employees: BTreeMap<String, Vec<Anomaly>> = day.employees
+                    .iter()
+                    .filter(|(_, employee)| !employee.anomalies.is_empty())
+                    .map(|(label, employee)| (label.clone(), employee.anomalies.clone()))
+                    .collect();
+
+                (*date, employees)
+            })
+            .filter(|(_, employees)| !employees.is_empty())
+            .collect()
+    }
+}
+
+impl WorkDay {
+    pub fn new(date: NaiveDate) -> WorkDay {
+        WorkDay { date, employees: BTreeMap::new() }
+    }
+
+    pub fn employee(&self, label: &str) -> Option<&EmployeeDay> {
+        self.employees.get(label)
+    }
+
+    pub fn total_minutes(&self) -> i32 {
+        self.employees.values().map(EmployeeDay::total_minutes).sum()
+    }
+
+    // The minutes per action per employee, the JSON shape of the first
+    // version of the API.
+    pub fn minutes_per_employee(&self) -> BTreeMap<String, BTreeMap<String, i32>> {
+        self.employees
+            .iter()
+            .map(|(label, employee)| (label.clone(), employee.minutes_per_action()))
+            .collect()
+    }
+}
+
+impl EmployeeDay {
+    pub fn new(employee_id: Option<u32>, name: String) -> EmployeeDay {
+        EmployeeDay {
+            employee_id,
+            name,
+            actions: BTreeMap::new(),
+            breaks: None,
+            totals: DayTotals::default(),
+            segments: vec![],
+            break_rules: vec![],
+            anomalies: vec![],
+        }
+    }
+
+    pub fn add_segment(&mut self, segment: Segment) {
+        if let Some(action) = segment.credited_action() {
+            let total = self.actions
+                .entry(action.clone())
+                .or_insert_with(|| ActionTotal { action: action.clone(), seconds: 0, minutes: 0 });
+            total.seconds += segment.seconds;
+        }
+
+        let position = self.segments
+            .iter()
+            .position(|other| other.start > segment.start)
+            .unwrap_or(self.segments.len());
+        self.segments.insert(position, segment);
+    }
+
+    // Rounds the seconds into the minutes that are reported.
+    pub fn summarize(&mut self, settings: &Settings) {
+        let policy = &settings.rounding;
+        let segments = &self.segments;
+
+        self.actions.values_mut().for_each(|total| {
+            total.minutes = policy.minutes(segments
+                .iter()
+                .filter(|segment| segment.credited_action() == Some(&total.action))
+                .map(|segment| segment.seconds));
+        });
+
+        let breaks: Vec<i64> = segments
+            .iter()
+            .filter(|segment| segment.kind == SegmentKind::Break && !segment.paid)
+            .map(|segment| segment.seconds)
+            .collect();
+        let break_minutes = policy.minutes(breaks.clone());
+        self.breaks = if settings.breaks.line_item {
+            Some(BreakTotal { count: breaks.len(), seconds: breaks.iter().sum(), minutes: break_minutes })
+        } else {
+            None
+        };
+
+        self.totals = DayTotals {
+            work: self.total_minutes(),
+            breaks: break_minutes,
+            presence: policy.minutes(segments.iter().map(|segment| segment.seconds)),
+        };
+    }
+
+    pub fn minutes(&self, action: &str) -> Option<i32> {
+        self.actions.get(action).map(|total| total.minutes)
+    }
+
+    // Only the actions, an action may be called anything so the break line
+    // item is left to `breaks`.
+    pub fn minutes_per_action(&self) -> BTreeMap<String, i32> {
+        self.actions
+            .values()
+            .map(|total| (total.action.clone(), total.minutes))
+            .collect()
+    }
+
+    pub fn total_minutes(&self) -> i32 {
+        self.actions.values().map(|total| total.minutes).sum()
+    }
+}